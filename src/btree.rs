@@ -1,133 +1,2448 @@
-use std::sync::{Arc, Mutex};
-use crate::page_manager::DiskPageManager;
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::io::{Read, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, TryLockError};
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::page_manager::{DiskPageManager, PageManagerError, Pager};
+use crate::pages::PageType;
 
 /// Type alias for on-disk page identifiers
 pub type PageId = u32;
 
+/// A key and its value, as returned by lookups and scans
+pub type Entry = (Vec<u8>, Vec<u8>);
+
+/// Bytes at the start of every node page: 1-byte node type tag, `u32` key count, a
+/// `u64` next-leaf pointer (unused by internal nodes), and the tree's `u32` order and
+/// `u32` fixed value width
+const NODE_HEADER_SIZE: usize = 1 + 4 + 8 + 4 + 4;
+
+/// Offset of the order in the node header, followed by the value width
+const NODE_ORDER_OFFSET: usize = 1 + 4 + 8;
+
+/// Value width stored in node headers of trees whose values are length-prefixed
+const VARIABLE_VALUE_WIDTH: u32 = u32::MAX;
+
+/// Length prefix in front of every key and value
+const LEN_PREFIX_SIZE: usize = 4;
+
+/// Size of a child pointer in an internal node
+const CHILD_PTR_SIZE: usize = 4;
+
+/// How long `try_search` sleeps between attempts to grab a contended lock
+const TRY_LOCK_BACKOFF: Duration = Duration::from_millis(1);
+
 /// Distinguishes leaf vs. internal nodes
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum NodeType {
+    /// Node holding entries
     Leaf,
+    /// Node holding separator keys and child pointers
     Internal,
 }
 
+/// How `debug_dump` renders keys, so binary keys don't garble log output
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyRender {
+    /// Interpret bytes as UTF-8, replacing invalid sequences with U+FFFD
+    Utf8Lossy,
+    /// Lowercase hex, two digits per byte
+    Hex,
+    /// Printable ASCII as is, everything else as `\xNN`-style escapes
+    Escaped,
+}
+
+impl KeyRender {
+    /// Render `bytes` according to this mode
+    pub fn render(&self, bytes: &[u8]) -> String {
+        match self {
+            KeyRender::Utf8Lossy => String::from_utf8_lossy(bytes).into_owned(),
+            KeyRender::Hex => bytes.iter().map(|b| format!("{:02x}", b)).collect(),
+            KeyRender::Escaped => bytes.escape_ascii().to_string(),
+        }
+    }
+}
+
+/// Flattens a `LeafIter` into individual entries, still loading one leaf at a time
+struct EntryIter<'a, P: Pager> {
+    leaves: LeafIter<'a, P>,
+    current: std::vec::IntoIter<(Vec<u8>, Vec<u8>)>,
+}
+
+impl<P: Pager> Iterator for EntryIter<'_, P> {
+    type Item = Result<(Vec<u8>, Vec<u8>), BTreeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(entry) = self.current.next() {
+                return Some(Ok(entry));
+            }
+            match self.leaves.next()? {
+                Ok(batch) => self.current = batch.entries.into_iter(),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+/// K-way merge over several trees, see `merge_iter`
+pub struct MergeIter<'a, P: Pager = DiskPageManager> {
+    sources: Vec<EntryIter<'a, P>>,
+    heads: Vec<Option<(Vec<u8>, Vec<u8>)>>,
+    started: bool,
+}
+
+impl<P: Pager> MergeIter<'_, P> {
+    /// Replace the head of source `idx` with its next entry
+    fn advance(&mut self, idx: usize) -> Result<(), BTreeError> {
+        self.heads[idx] = self.sources[idx].next().transpose()?;
+        Ok(())
+    }
+}
+
+impl<P: Pager> Iterator for MergeIter<'_, P> {
+    type Item = Result<(Vec<u8>, Vec<u8>, usize), BTreeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if !self.started {
+            self.started = true;
+            for idx in 0..self.sources.len() {
+                if let Err(e) = self.advance(idx) {
+                    return Some(Err(e));
+                }
+            }
+        }
+
+        // Smallest head wins; equal keys come out in tree order
+        let (_, idx) = self.heads
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, head)| head.as_ref().map(|(key, _)| (key, idx)))
+            .min()?;
+        let (key, value) = self.heads[idx].take()?;
+        if let Err(e) = self.advance(idx) {
+            return Some(Err(e));
+        }
+        Some(Ok((key, value, idx)))
+    }
+}
+
+/// Merge the sorted contents of `trees` into one globally sorted stream of
+/// `(key, value, tree_index)`. Keys present in several trees are yielded once per tree, in
+/// tree order, leaving collision handling to the caller. Each tree is read one leaf at a time.
+pub fn merge_iter<'a, P: Pager>(trees: &[&'a BTreeEngine<P>]) -> MergeIter<'a, P> {
+    MergeIter {
+        sources: trees.iter().map(|tree| EntryIter {
+            leaves: tree.iter_leaves(),
+            current: vec![].into_iter(),
+        }).collect(),
+        heads: vec![None; trees.len()],
+        started: false,
+    }
+}
+
+/// Which entry `BTreeEngine::dedup` keeps out of a run of equal keys
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DedupPolicy {
+    /// Keep the entry that comes first in leaf order
+    KeepFirst,
+    /// Keep the entry that comes last in leaf order
+    KeepLast,
+}
+
+/// What `BTreeEngine::bulk_load` does when two consecutive input keys are equal
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DuplicatePolicy {
+    /// Fail with `BTreeError::DuplicateInput`
+    #[default]
+    Error,
+    /// Keep the value that came first in the input
+    KeepFirst,
+    /// Keep the value that came last in the input
+    KeepLast,
+}
+
+/// When `BTreeEngine::bulk_load` sets the `next_leaf` links of the leaves it writes. Either
+/// way the finished tree has a complete leaf chain.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LeafLinking {
+    /// Link every leaf to its successor as it is written
+    #[default]
+    Eager,
+    /// Write leaves unlinked and chain them in a final pass over the leaf level, so the
+    /// loading loop never has to know a leaf's successor
+    Lazy,
+}
+
+/// Running totals of the operations an engine has performed since it was created
+#[derive(Default)]
+struct OpCounters {
+    inserts: AtomicU64,
+    updates: AtomicU64,
+    deletes: AtomicU64,
+    searches: AtomicU64,
+}
+
+/// Dirty-page table of an open `Transaction`
+struct TxnState {
+    /// Root page when the transaction began, restored on rollback
+    root_at_begin: PageId,
+    /// Encoded node pages written by the transaction, not yet handed to the pager
+    pages: HashMap<PageId, Vec<u8>>,
+    /// Pages allocated by the transaction, given back on rollback
+    allocated: Vec<PageId>,
+    /// Pages the transaction stopped using. The committed tree may still reference them, so
+    /// they are only freed on commit.
+    freed: Vec<PageId>,
+    /// Mutations made by the transaction, added to the engine's counters on commit
+    counters: OpCounters,
+    /// Keys the transaction inserted, updated or deleted
+    changed_keys: BTreeSet<Vec<u8>>,
+}
+
+/// Group of mutations on a `BTreeEngine` that reach its pager all together on `commit`, or
+/// not at all. Obtained from `BTreeEngine::begin`.
+///
+/// Node writes go to a dirty-page table that the transaction's own reads consult first.
+/// Dropping the transaction without committing rolls it back.
+pub struct Transaction<'a, P: Pager = DiskPageManager> {
+    engine: &'a mut BTreeEngine<P>,
+}
+
+impl<P: Pager> Transaction<'_, P> {
+    /// Insert a key/value pair, see `BTreeEngine::insert`.
+    pub fn insert(&mut self, key: &[u8], value: &[u8]) -> Result<(), BTreeError> {
+        self.engine.insert(key, value)
+    }
+
+    /// Overwrite the value of an existing key, see `BTreeEngine::update`.
+    pub fn update(&mut self, key: &[u8], value: &[u8]) -> Result<(), BTreeError> {
+        self.engine.update(key, value)
+    }
+
+    /// Delete a key, see `BTreeEngine::delete`.
+    pub fn delete(&mut self, key: &[u8]) -> Result<(), BTreeError> {
+        self.engine.delete(key)
+    }
+
+    /// Search for a key, seeing the transaction's own uncommitted writes.
+    pub fn search(&self, key: &[u8]) -> Result<Option<Vec<u8>>, BTreeError> {
+        self.engine.search(key)
+    }
+
+    /// Keys inserted, updated or deleted by the transaction so far, in key order and each
+    /// listed once. Deleting a key that isn't present doesn't change it.
+    pub fn changed_keys(&self) -> Result<Vec<Vec<u8>>, BTreeError> {
+        let txn = self.engine.lock_txn()?;
+        let mut keys = vec![];
+        let mut used = 0;
+        for key in txn.as_ref().map(|txn| &txn.changed_keys).into_iter().flatten() {
+            self.engine.charge_memory(&mut used, key.len())?;
+            keys.push(key.clone());
+        }
+        Ok(keys)
+    }
+
+    /// Hand every dirty page and the tree's root to the pager's `commit_pages` in one batch,
+    /// which a `DiskPageManager` logs to its write-ahead log first, then release the pages
+    /// the transaction freed. If the pager fails, the transaction is rolled back.
+    pub fn commit(self) -> Result<(), BTreeError> {
+        self.engine.commit_txn()
+    }
+
+    /// Discard every change made by the transaction. Same as dropping it.
+    pub fn rollback(self) {}
+}
+
+impl<P: Pager> Drop for Transaction<'_, P> {
+    fn drop(&mut self) {
+        // Nothing is left to roll back after a commit
+        let _ = self.engine.rollback_txn();
+    }
+}
+
+/// Snapshot of an engine's shape and activity, see `BTreeEngine::report`
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EngineReport {
+    /// Number of entries stored in the leaves
+    pub key_count: u64,
+    /// Levels from the root down to the leaves, 1 for a lone root leaf
+    pub height: usize,
+    /// Pages reachable from the root
+    pub page_footprint: u64,
+    /// Fraction of page reads served by the page cache, `None` if nothing went through one
+    pub cache_hit_ratio: Option<f64>,
+    /// Successful `insert` calls
+    pub inserts: u64,
+    /// Successful `update` calls
+    pub updates: u64,
+    /// Successful `delete` calls
+    pub deletes: u64,
+    /// `search` and `try_search` calls
+    pub searches: u64,
+    /// Most bytes of keys and values a single collecting operation (e.g. `collect_range`)
+    /// accumulated, to size `with_memory_limit` against
+    pub peak_operation_memory: u64,
+}
+
+/// A leaf `build_sorted` is still filling, written once the leaf after it is known
+struct PendingLeaf {
+    page_id: PageId,
+    keys: Vec<Vec<u8>>,
+    values: Vec<Vec<u8>>,
+}
+
 /// Result of splitting a full node
 pub struct SplitResult {
+    /// Page the upper half of the node moved to
     pub new_page: PageId,
+    /// Separator to insert into the parent in front of `new_page`
     pub promoted_key: Vec<u8>,
 }
 
 /// In-memory representation of a B-Tree node
 pub enum Node {
+    /// Leaf holding entries in key order
     Leaf {
+        /// Page the node lives on
         page_id: PageId,
+        /// Sorted keys
         keys: Vec<Vec<u8>>,
+        /// Value of each key, at the same index
         values: Vec<Vec<u8>>,
+        /// Next leaf in key order, `None` for the last one
         next_leaf: Option<PageId>,
     },
+    /// Internal node routing searches to its children
     Internal {
+        /// Page the node lives on
         page_id: PageId,
+        /// Sorted separator keys
         keys: Vec<Vec<u8>>,
+        /// Child pages, one more than there are keys
         children: Vec<PageId>,
     },
 }
 
+impl Node {
+    /// Number of keys stored in the node
+    fn key_count(&self) -> usize {
+        match self {
+            Node::Leaf { keys, .. } | Node::Internal { keys, .. } => keys.len(),
+        }
+    }
+
+    /// Page the node lives on
+    fn page_id(&self) -> PageId {
+        match self {
+            Node::Leaf { page_id, .. } | Node::Internal { page_id, .. } => *page_id,
+        }
+    }
+}
+
+/// All entries of a single leaf page, as yielded by `BTreeEngine::iter_leaves`
+#[derive(Debug, Clone, PartialEq)]
+pub struct LeafBatch {
+    /// Leaf page the entries were read from
+    pub page_id: PageId,
+    /// Entries of the leaf in key order
+    pub entries: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+/// Iterator over the leaf chain, one `LeafBatch` per leaf page in key order
+pub struct LeafIter<'a, P: Pager = DiskPageManager> {
+    engine: &'a BTreeEngine<P>,
+    next_page: Option<PageId>,
+    started: bool,
+}
+
+impl<P: Pager> Iterator for LeafIter<'_, P> {
+    type Item = Result<LeafBatch, BTreeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if !self.started {
+            self.started = true;
+            match self.engine.leftmost_leaf() {
+                Ok(page_id) => self.next_page = Some(page_id),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+
+        let page_id = self.next_page.take()?;
+        match self.engine.load_node(page_id) {
+            Ok(Node::Leaf { keys, values, next_leaf, .. }) => {
+                self.next_page = next_leaf;
+                Some(Ok(LeafBatch { page_id, entries: keys.into_iter().zip(values).collect() }))
+            }
+            Ok(Node::Internal { .. }) => Some(Err(BTreeError::Corruption(
+                format!("page {} in the leaf chain is not a leaf", page_id),
+            ))),
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+/// Entries with keys in `[start, end)`, see `BTreeEngine::range`.
+///
+/// Holds the current leaf plus up to `prefetch` leaves read ahead in memory, and takes the
+/// page manager lock only while loading leaves, so other readers can interleave with a
+/// long scan.
+pub struct RangeIter<'a, P: Pager = DiskPageManager> {
+    engine: &'a BTreeEngine<P>,
+    end: Option<Vec<u8>>,
+    current: std::vec::IntoIter<(Vec<u8>, Vec<u8>)>,
+    /// Entries of the leaves read ahead of `current`, in chain order
+    ahead: VecDeque<Vec<Entry>>,
+    prefetch: usize,
+    /// Next leaf in the chain that hasn't been loaded yet
+    next_page: Option<PageId>,
+}
+
+impl<P: Pager> RangeIter<'_, P> {
+    /// Load the leaf `next_page` points at and advance it, stopping the chain at the first
+    /// leaf that reaches `end`
+    fn load_next_leaf(&mut self) -> Result<Option<Vec<Entry>>, BTreeError> {
+        let Some(page_id) = self.next_page.take() else {
+            return Ok(None);
+        };
+        let Node::Leaf { keys, values, next_leaf, .. } = self.engine.load_node(page_id)? else {
+            return Err(BTreeError::Corruption("leaf chain points at an internal node".to_string()));
+        };
+        let reaches_end = self.end.as_ref().is_some_and(|end| keys.last().is_some_and(|last| last >= end));
+        self.next_page = next_leaf.filter(|_| !reaches_end);
+        Ok(Some(keys.into_iter().zip(values).collect()))
+    }
+
+    /// Read ahead until `prefetch` leaves are buffered or the range runs out
+    fn fill_ahead(&mut self) -> Result<(), BTreeError> {
+        while self.ahead.len() < self.prefetch {
+            match self.load_next_leaf()? {
+                Some(entries) => self.ahead.push_back(entries),
+                None => break,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<P: Pager> Iterator for RangeIter<'_, P> {
+    type Item = Result<(Vec<u8>, Vec<u8>), BTreeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((key, value)) = self.current.next() {
+                if self.end.as_ref().is_some_and(|end| &key >= end) {
+                    self.next_page = None;
+                    self.ahead.clear();
+                    self.current = vec![].into_iter();
+                    return None;
+                }
+                return Some(Ok((key, value)));
+            }
+
+            let entries = match self.ahead.pop_front() {
+                Some(entries) => entries,
+                None => match self.load_next_leaf() {
+                    Ok(entries) => entries?,
+                    Err(e) => return Some(Err(e)),
+                },
+            };
+            self.current = entries.into_iter();
+            if let Err(e) = self.fill_ahead() {
+                return Some(Err(e));
+            }
+        }
+    }
+}
+
 /// Errors raised by BTreeEngine operations
 #[derive(Debug)]
 pub enum BTreeError {
+    /// Reading or writing a stream, e.g. during export or import, failed
     Io(std::io::Error),
+    /// A page doesn't hold a well-formed node, or nodes contradict each other
     Corruption(String),
+    /// The key to update doesn't exist
     NotFound,
+    /// Insert of a key that already exists while in strict unique mode
+    DuplicateKey,
+    /// Value length doesn't match the tree's fixed value width
+    ValueWidthMismatch {
+        /// Fixed value width of the tree
+        expected: usize,
+        /// Length of the rejected value
+        got: usize,
+    },
+    /// The engine was configured with parameters it can't work with
+    InvalidConfig(String),
+    /// Error bubbled up from the underlying page manager
+    PageManager(PageManagerError),
+    /// The page manager mutex was poisoned by a panicking thread
+    LockPoisoned,
+    /// The page manager lock could not be acquired within the requested timeout
+    WouldBlock,
+    /// `begin` was called while another transaction on the engine is still open
+    TransactionOpen,
+    /// `bulk_load` input whose keys aren't ascending; `key` is the first offender
+    UnsortedInput {
+        /// First key smaller than the one before it
+        key: Vec<u8>,
+    },
+    /// `bulk_load` input repeating `key` under `DuplicatePolicy::Error`
+    DuplicateInput {
+        /// Key that appeared more than once
+        key: Vec<u8>,
+    },
+    /// A node's encoding doesn't fit in the usable part of its page
+    NodeOverflow {
+        /// Page the node was written to
+        page_id: PageId,
+        /// Bytes the encoded node takes
+        needed: usize,
+        /// Usable bytes of the page
+        available: usize,
+    },
+    /// A collecting operation accumulated more than the engine's per-operation memory limit;
+    /// use the iterator form instead
+    MemoryLimitExceeded {
+        /// Configured limit in bytes
+        limit: usize,
+    },
     // Extend with SplitFailed, Underflow, etc.
 }
 
+impl std::fmt::Display for BTreeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BTreeError::Io(e) => write!(f, "io error: {}", e),
+            BTreeError::Corruption(msg) => write!(f, "corrupt tree: {}", msg),
+            BTreeError::InvalidConfig(msg) => write!(f, "invalid configuration: {}", msg),
+            BTreeError::PageManager(e) => write!(f, "page manager error: {}", e),
+            BTreeError::NodeOverflow { page_id, needed, available } => write!(
+                f, "node on page {} needs {} bytes but only {} are available", page_id, needed, available,
+            ),
+            other => write!(f, "{:?}", other),
+        }
+    }
+}
+
+impl std::error::Error for BTreeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BTreeError::Io(e) => Some(e),
+            BTreeError::PageManager(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<PageManagerError> for BTreeError {
+    fn from(error: PageManagerError) -> Self {
+        BTreeError::PageManager(error)
+    }
+}
+
+/// Index of the child to descend into for `key` in an internal node with separator `keys`.
+/// Separators are the first key of their right subtree, so equal keys go right.
+fn child_index(keys: &[Vec<u8>], key: &[u8]) -> usize {
+    keys.partition_point(|k| k.as_slice() <= key)
+}
+
+/// Bounds-checked cursor over the bytes of a node page
+struct NodeReader<'a> {
+    page_id: PageId,
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> NodeReader<'a> {
+    /// Next `len` bytes, or `BTreeError::Corruption` if they run past the page
+    fn take(&mut self, len: usize) -> Result<&'a [u8], BTreeError> {
+        let end = self.pos.checked_add(len).filter(|&end| end <= self.buf.len()).ok_or_else(|| {
+            BTreeError::Corruption(format!("node on page {} runs past the end of the page", self.page_id))
+        })?;
+        let bytes = &self.buf[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32, BTreeError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, BTreeError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    /// A `u32` length prefix followed by that many bytes
+    fn prefixed(&mut self) -> Result<Vec<u8>, BTreeError> {
+        let len = self.u32()? as usize;
+        Ok(self.take(len)?.to_vec())
+    }
+}
+
+/// Append `bytes` to `out` behind a `u32` length prefix
+fn put_prefixed(out: &mut Vec<u8>, bytes: &[u8]) {
+    out.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+    out.extend_from_slice(bytes);
+}
+
+/// Move the last entry of `left` to the front of its right sibling `node`, keeping the parent
+/// `separator` between them correct
+fn borrow_from_left(left: &mut Node, node: &mut Node, separator: &mut Vec<u8>) -> Result<(), BTreeError> {
+    match (left, node) {
+        (Node::Leaf { keys: lk, values: lv, .. }, Node::Leaf { keys: nk, values: nv, .. }) => {
+            nk.insert(0, lk.pop().expect("lending leaf has keys"));
+            nv.insert(0, lv.pop().expect("lending leaf has values"));
+            *separator = nk[0].clone();
+        }
+        (Node::Internal { keys: lk, children: lc, .. }, Node::Internal { keys: nk, children: nc, .. }) => {
+            let lent = lk.pop().expect("lending node has keys");
+            nk.insert(0, std::mem::replace(separator, lent));
+            nc.insert(0, lc.pop().expect("lending node has children"));
+        }
+        _ => return Err(BTreeError::Corruption("siblings are at different levels".to_string())),
+    }
+    Ok(())
+}
+
+/// Move the first entry of `right` to the end of its left sibling `node`, keeping the parent
+/// `separator` between them correct
+fn borrow_from_right(node: &mut Node, right: &mut Node, separator: &mut Vec<u8>) -> Result<(), BTreeError> {
+    match (node, right) {
+        (Node::Leaf { keys: nk, values: nv, .. }, Node::Leaf { keys: rk, values: rv, .. }) => {
+            nk.push(rk.remove(0));
+            nv.push(rv.remove(0));
+            *separator = rk[0].clone();
+        }
+        (Node::Internal { keys: nk, children: nc, .. }, Node::Internal { keys: rk, children: rc, .. }) => {
+            let lent = rk.remove(0);
+            nk.push(std::mem::replace(separator, lent));
+            nc.push(rc.remove(0));
+        }
+        _ => return Err(BTreeError::Corruption("siblings are at different levels".to_string())),
+    }
+    Ok(())
+}
+
+/// Append everything in `right` to its left sibling `left`. Internal nodes also pull down the
+/// parent `separator` that sat between them.
+fn merge_nodes(left: &mut Node, right: Node, separator: Vec<u8>) -> Result<(), BTreeError> {
+    match (left, right) {
+        (Node::Leaf { keys: lk, values: lv, next_leaf: ln, .. }, Node::Leaf { keys, values, next_leaf, .. }) => {
+            lk.extend(keys);
+            lv.extend(values);
+            *ln = next_leaf;
+        }
+        (Node::Internal { keys: lk, children: lc, .. }, Node::Internal { keys, children, .. }) => {
+            lk.push(separator);
+            lk.extend(keys);
+            lc.extend(children);
+        }
+        _ => return Err(BTreeError::Corruption("siblings are at different levels".to_string())),
+    }
+    Ok(())
+}
+
+/// Check that a full node of the given order fits in a page even when every key and value is
+/// empty. If it doesn't, splits can't make room and inserts would fail on any data.
+fn check_order_fits(order: usize, page_size: u64) -> Result<(), BTreeError> {
+    let full_internal = NODE_HEADER_SIZE + order * CHILD_PTR_SIZE + (order - 1) * LEN_PREFIX_SIZE;
+    let full_leaf = NODE_HEADER_SIZE + (order - 1) * 2 * LEN_PREFIX_SIZE;
+    let needed = full_internal.max(full_leaf) as u64;
+    if needed > page_size {
+        return Err(BTreeError::InvalidConfig(format!(
+            "order {} needs at least {} bytes per node but pages are {} bytes", order, needed, page_size,
+        )));
+    }
+    Ok(())
+}
+
+/// Order and fixed value width stored in the header of node `page_id`, i.e. the settings of
+/// the tree it was written by
+fn stored_settings<P: Pager>(page_manager: &mut P, page_id: PageId) -> Result<(usize, Option<usize>), BTreeError> {
+    let mut buf = vec![0u8; page_manager.page_size() as usize];
+    page_manager.read_page(page_id, &mut buf)?;
+    let mut reader = NodeReader { page_id, buf: &buf, pos: NODE_ORDER_OFFSET };
+    let order = reader.u32()? as usize;
+    if order < 3 {
+        return Err(BTreeError::Corruption(format!("node on page {} records order {}", page_id, order)));
+    }
+    let width = reader.u32()?;
+    Ok((order, (width != VARIABLE_VALUE_WIDTH).then_some(width as usize)))
+}
+
+/// Whether the leftmost leaf under `root` is front-coded, i.e. whether the tree was written
+/// with `with_front_coded_leaves`
+fn stored_front_coding<P: Pager>(page_manager: &mut P, root: PageId) -> Result<bool, BTreeError> {
+    let mut buf = vec![0u8; page_manager.page_size() as usize];
+    let mut page_id = root;
+    // No tree is this deep; a longer walk means the child pointers loop
+    for _ in 0..64 {
+        page_manager.read_page(page_id, &mut buf)?;
+        let mut reader = NodeReader { page_id, buf: &buf, pos: 0 };
+        match PageType::from_u8(reader.take(1)?[0]) {
+            Ok(PageType::Internal) => {
+                reader.pos = NODE_HEADER_SIZE;
+                page_id = reader.u32()?;
+            }
+            Ok(page_type) => return Ok(page_type == PageType::FrontCodedLeaf),
+            Err(_) => return Err(BTreeError::Corruption(format!("page {} has unknown node tag {}", page_id, buf[0]))),
+        }
+    }
+    Err(BTreeError::Corruption(format!("leftmost path from page {} doesn't reach a leaf", root)))
+}
+
+/// Length of the prefix `a` and `b` share
+fn shared_prefix_len(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b).take_while(|(x, y)| x == y).count()
+}
+
 /// Core engine driving B-Tree operations on top of a Pager
-pub struct BTreeEngine {
-    page_manager: Arc<Mutex<DiskPageManager>>,
+pub struct BTreeEngine<P: Pager = DiskPageManager> {
+    page_manager: Arc<Mutex<P>>,
     order: usize,
     root_page: PageId,
+    strict_unique: bool,
+    fixed_value_width: Option<usize>,
+    front_coded_leaves: bool,
+    memory_limit: Option<usize>,
+    /// Most bytes a single collecting operation accumulated, see `EngineReport`
+    peak_memory: AtomicU64,
+    counters: OpCounters,
+    /// Open transaction, if any. Locked after the page manager when both are needed.
+    txn: Mutex<Option<TxnState>>,
+    /// Pages the tree stopped using that the header may still point at, freed by `flush`
+    retired_pages: Vec<PageId>,
 }
 
-impl BTreeEngine {
-    /// Create or open a B-Tree with given order (max children per internal node)
+impl<P: Pager> BTreeEngine<P> {
+    /// Create or open a B-Tree with given order (max children per internal node).
+    ///
+    /// An existing tree keeps the order, fixed value width and leaf encoding recorded in its
+    /// nodes, e.g. by `reorganize`, and `order` only applies to a newly created tree.
     pub fn new(
-        page_manager: Arc<Mutex<DiskPageManager>>,
+        page_manager: Arc<Mutex<P>>,
+        order: usize,
+    ) -> Result<Self, BTreeError> {
+        if order < 3 {
+            return Err(BTreeError::InvalidConfig(format!("order must be at least 3, got {}", order)));
+        }
+
+        let mut guard = page_manager.lock().map_err(|_| BTreeError::LockPoisoned)?;
+        let existing_root = guard.schema_root_page();
+        let (root_page, order, fixed_value_width, front_coded_leaves) = if existing_root != 0 {
+            let (order, width) = stored_settings(&mut *guard, existing_root)?;
+            (existing_root, order, width, stored_front_coding(&mut *guard, existing_root)?)
+        } else {
+            check_order_fits(order, guard.page_size())?;
+            (guard.alloc_page()?, order, None, false)
+        };
+        drop(guard);
+
+        let mut engine = Self {
+            page_manager,
+            order,
+            root_page,
+            strict_unique: false,
+            fixed_value_width,
+            front_coded_leaves,
+            memory_limit: None,
+            peak_memory: AtomicU64::new(0),
+            counters: OpCounters::default(),
+            txn: Mutex::new(None),
+            retired_pages: vec![],
+        };
+        if existing_root == 0 {
+            engine.write_node(Node::Leaf {
+                page_id: root_page,
+                keys: vec![],
+                values: vec![],
+                next_leaf: None,
+            })?;
+            engine.flush()?;
+        }
+        Ok(engine)
+    }
+
+    /// Create a tree on `page_manager` from `pairs`, which must be in ascending key order,
+    /// otherwise `BTreeError::UnsortedInput` is returned and nothing is kept. Runs of equal
+    /// keys are handled according to `duplicates`, and `linking` picks when the leaf chain is
+    /// built.
+    ///
+    /// Much faster than inserting one by one: leaves are filled to `order - 1` keys in a
+    /// single pass and the internal levels are built bottom-up from their first keys, so no
+    /// node ever splits. Only the last node of each level may hold fewer, and it takes entries
+    /// from its left neighbour when it would otherwise underflow. The pager must not hold a
+    /// non-empty tree yet; the new root is persisted in its header.
+    pub fn bulk_load(
+        page_manager: Arc<Mutex<P>>,
         order: usize,
+        pairs: impl Iterator<Item = (Vec<u8>, Vec<u8>)>,
+        duplicates: DuplicatePolicy,
+        linking: LeafLinking,
     ) -> Result<Self, BTreeError> {
-        // allocate or load root_page from header
-        unimplemented!()
+        let mut engine = Self::new(page_manager, order)?;
+        if !engine.is_empty()? {
+            return Err(BTreeError::InvalidConfig("bulk_load needs a pager without an existing tree".to_string()));
+        }
+
+        let new_root = engine.build_detached(pairs, Some(duplicates), linking)?;
+        engine.retired_pages.push(engine.root_page);
+        engine.root_page = new_root;
+        engine.flush()?;
+        Ok(engine)
+    }
+
+    /// Make `insert` fail with `BTreeError::DuplicateKey` instead of overwriting an existing
+    /// key, so accidental double inserts surface as errors. Overwrites then have to go
+    /// through `update`.
+    pub fn with_strict_unique(mut self, strict_unique: bool) -> Self {
+        self.strict_unique = strict_unique;
+        self
+    }
+
+    /// Require every value to be exactly `width` bytes, e.g. for counter or row-id trees.
+    /// Leaves then store values without a length prefix, and inserts or updates of any other
+    /// width fail with `BTreeError::ValueWidthMismatch`.
+    ///
+    /// The width is recorded in every node, so a reopened tree picks it up by itself. Leaves
+    /// written with a different width can't be decoded and fail to load with
+    /// `BTreeError::InvalidConfig`, so set this only on a new or empty tree.
+    pub fn with_fixed_value_width(mut self, width: usize) -> Self {
+        self.fixed_value_width = Some(width);
+        self
+    }
+
+    /// Store each leaf key after the first as the length of the prefix it shares with the
+    /// previous key plus the remaining suffix, which packs sorted keys with long common
+    /// prefixes more densely. Every leaf records its encoding, so leaves written either way
+    /// load fine and a reopened tree picks the setting up by itself.
+    pub fn with_front_coded_leaves(mut self, front_coded: bool) -> Self {
+        self.front_coded_leaves = front_coded;
+        self
+    }
+
+    /// Cap how many bytes of keys and values a single collecting operation (`collect_range`,
+    /// `scan_after`, `search_all`, `find_duplicate_keys`, `Transaction::changed_keys`) may
+    /// accumulate before failing with `BTreeError::MemoryLimitExceeded`. Iterators such as
+    /// `range` and `iter_leaves` hold one leaf at a time and aren't affected, and neither are
+    /// rebuilds like `dedup` and `reorganize`, which stream the leaves.
+    pub fn with_memory_limit(mut self, bytes: usize) -> Self {
+        self.memory_limit = Some(bytes);
+        self
     }
 
     /// Insert a key/value pair into the tree
     pub fn insert(&mut self, key: &[u8], value: &[u8]) -> Result<(), BTreeError> {
-        unimplemented!()
+        self.check_value_width(value)?;
+        if self.strict_unique && self.key_exists(key)? {
+            return Err(BTreeError::DuplicateKey);
+        }
+        self.upsert(key, value, false)?;
+        self.record_mutation(key, |counters| &counters.inserts)
+    }
+
+    /// Insert a key/value pair, keeping any entries already stored under `key` rather than
+    /// overwriting them. Turns the tree into a multi-map, e.g. for secondary indexes mapping
+    /// one indexed value to many row ids; read the values back with `search_all`.
+    ///
+    /// Duplicates of a key are kept contiguous and in insertion order. `search`, `update` and
+    /// `delete` act on a single one of them, and `find_duplicate_keys` reports them.
+    pub fn insert_dup(&mut self, key: &[u8], value: &[u8]) -> Result<(), BTreeError> {
+        self.check_value_width(value)?;
+        self.upsert(key, value, true)?;
+        self.record_mutation(key, |counters| &counters.inserts)
+    }
+
+    /// Every value stored under `key`, in insertion order, empty if the key is absent
+    pub fn search_all(&self, key: &[u8]) -> Result<Vec<Vec<u8>>, BTreeError> {
+        // A run of duplicates can straddle a split, so start at the leftmost leaf that may
+        // hold the key rather than where `child_index` would lead
+        let mut page_id = self.root_page;
+        while let Node::Internal { keys, children, .. } = self.load_node(page_id)? {
+            page_id = children[keys.partition_point(|k| k.as_slice() < key)];
+        }
+
+        let mut found = vec![];
+        let mut used = 0;
+        loop {
+            let Node::Leaf { keys, values, next_leaf, .. } = self.load_node(page_id)? else {
+                return Err(BTreeError::Corruption("leaf chain points at an internal node".to_string()));
+            };
+            let start = keys.partition_point(|k| k.as_slice() < key);
+            let end = keys.partition_point(|k| k.as_slice() <= key);
+            for value in values.into_iter().take(end).skip(start) {
+                self.charge_memory(&mut used, value.len())?;
+                found.push(value);
+            }
+            match next_leaf {
+                Some(next) if end == keys.len() => page_id = next,
+                _ => return Ok(found),
+            }
+        }
+    }
+
+    /// Replace the value of an existing key, failing with `BTreeError::NotFound` if it's absent
+    pub fn update(&mut self, key: &[u8], value: &[u8]) -> Result<(), BTreeError> {
+        self.check_value_width(value)?;
+        if !self.key_exists(key)? {
+            return Err(BTreeError::NotFound);
+        }
+        self.upsert(key, value, false)?;
+        self.record_mutation(key, |counters| &counters.updates)
     }
 
     /// Search for a key, returning its value if found
     pub fn search(&self, key: &[u8]) -> Result<Option<Vec<u8>>, BTreeError> {
-        unimplemented!()
+        self.counters.searches.fetch_add(1, Ordering::Relaxed);
+        let mut page_manager = self.lock_page_manager()?;
+        self.search_locked(&mut page_manager, key)
+    }
+
+    /// Like `search`, but gives up with `BTreeError::WouldBlock` if the page manager lock
+    /// can't be acquired within `timeout`. Useful for callers that must bound their latency
+    /// while a long writer holds the lock.
+    pub fn try_search(&self, key: &[u8], timeout: Duration) -> Result<Option<Vec<u8>>, BTreeError> {
+        self.counters.searches.fetch_add(1, Ordering::Relaxed);
+        let deadline = Instant::now() + timeout;
+        loop {
+            match self.page_manager.try_lock() {
+                Ok(mut page_manager) => return self.search_locked(&mut page_manager, key),
+                Err(TryLockError::Poisoned(_)) => return Err(BTreeError::LockPoisoned),
+                Err(TryLockError::WouldBlock) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Err(BTreeError::WouldBlock);
+                    }
+                    thread::sleep((deadline - now).min(TRY_LOCK_BACKOFF));
+                }
+            }
+        }
+    }
+
+    /// Search for a key, returning a copy of `default` if it isn't in the tree
+    pub fn get_or(&self, key: &[u8], default: &[u8]) -> Result<Vec<u8>, BTreeError> {
+        Ok(self.search(key)?.unwrap_or_else(|| default.to_vec()))
+    }
+
+    /// Check whether `key` is present without copying its value out
+    pub fn contains(&self, key: &[u8]) -> Result<bool, BTreeError> {
+        Ok(self.search(key)?.is_some())
+    }
+
+    /// Check a batch of keys for presence, returning one flag per key in input order.
+    ///
+    /// The keys are sorted and resolved with a single descent followed by a walk along the
+    /// leaf chain, so large batches touch every leaf at most once.
+    pub fn multi_contains(&self, keys: &[&[u8]]) -> Result<Vec<bool>, BTreeError> {
+        let mut found = vec![false; keys.len()];
+        if keys.is_empty() {
+            return Ok(found);
+        }
+
+        let mut order: Vec<usize> = (0..keys.len()).collect();
+        order.sort_by(|&a, &b| keys[a].cmp(keys[b]));
+
+        let mut leaf = self.load_node(self.leaf_for(keys[order[0]])?)?;
+        for idx in order {
+            let key = keys[idx];
+            loop {
+                let Node::Leaf { keys: leaf_keys, next_leaf, .. } = &leaf else {
+                    return Err(BTreeError::Corruption("leaf chain points at an internal node".to_string()));
+                };
+                let past_leaf = leaf_keys.last().is_none_or(|last| last.as_slice() < key);
+                match next_leaf {
+                    Some(next) if past_leaf => leaf = self.load_node(*next)?,
+                    _ => break,
+                }
+            }
+            if let Node::Leaf { keys: leaf_keys, .. } = &leaf {
+                found[idx] = leaf_keys.binary_search_by(|k| k.as_slice().cmp(key)).is_ok();
+            }
+        }
+
+        Ok(found)
+    }
+
+    /// Insert a value that is already in the engine's on-disk encoding, storing it verbatim.
+    ///
+    /// This is an escape hatch for copy-style operations (e.g. moving entries between trees)
+    /// that would otherwise decode and re-encode every value. It skips `insert`'s policy
+    /// checks: an existing key is overwritten even in strict unique mode, and the value width
+    /// is only checked by the leaf encoder, which can't store a mismatched fixed-width value.
+    pub fn insert_raw(&mut self, key: &[u8], raw_value: &[u8]) -> Result<(), BTreeError> {
+        self.upsert(key, raw_value, false)?;
+        self.record_mutation(key, |counters| &counters.inserts)
+    }
+
+    /// Fetch a value in its on-disk encoding, skipping any decoding. Counterpart of
+    /// `insert_raw`; like `key_exists`, it isn't counted as a search.
+    pub fn get_raw(&self, key: &[u8]) -> Result<Option<Vec<u8>>, BTreeError> {
+        let mut page_manager = self.lock_page_manager()?;
+        self.search_locked(&mut page_manager, key)
     }
 
     /// Delete a key (and its value) from the tree
+    ///
+    /// Deleting a key that isn't present is a no-op. A leaf left below `order / 2` keys borrows
+    /// an entry from a sibling or merges with it, which can cascade up to the root; a root left
+    /// with a single child is replaced by that child.
     pub fn delete(&mut self, key: &[u8]) -> Result<(), BTreeError> {
-        unimplemented!()
+        let mut path = vec![];
+        let mut page_id = self.root_page;
+        let (mut keys, mut values, next_leaf) = loop {
+            match self.load_node(page_id)? {
+                Node::Internal { keys, children, .. } => {
+                    let idx = child_index(&keys, key);
+                    path.push((page_id, idx));
+                    page_id = children[idx];
+                }
+                Node::Leaf { keys, values, next_leaf, .. } => break (keys, values, next_leaf),
+            }
+        };
+
+        let Ok(idx) = keys.binary_search_by(|k| k.as_slice().cmp(key)) else {
+            return Ok(());
+        };
+        keys.remove(idx);
+        values.remove(idx);
+        let underflowing = keys.len() < self.min_keys(NodeType::Leaf);
+        self.write_node(Node::Leaf { page_id, keys, values, next_leaf })?;
+        self.record_mutation(key, |counters| &counters.deletes)?;
+
+        if underflowing && !path.is_empty() {
+            self.rebalance(path, page_id)?;
+        }
+        Ok(())
     }
 
-    //—— INTERNAL HELPERS —————————————————————————————————————————
+    /// Summarize the tree's shape and the operations performed on it into one struct, e.g. for
+    /// a monitoring endpoint to scrape periodically. Walks every leaf to count keys.
+    pub fn report(&self) -> Result<EngineReport, BTreeError> {
+        let key_count = self.len()?;
 
-    /// Split a full leaf node, returning new page and key to promote
-    fn split_leaf(&mut self, page_id: PageId) -> Result<SplitResult, BTreeError> {
-        unimplemented!()
+        let mut height = 1;
+        let mut page_id = self.root_page;
+        while let Node::Internal { children, .. } = self.load_node(page_id)? {
+            page_id = children[0];
+            height += 1;
+        }
+
+        let cache = self.lock_page_manager()?.cache_stats();
+        let lookups = cache.hits + cache.misses;
+        let cache_hit_ratio = (lookups > 0).then(|| cache.hits as f64 / lookups as f64);
+
+        Ok(EngineReport {
+            key_count,
+            height,
+            page_footprint: self.page_footprint()?,
+            cache_hit_ratio,
+            inserts: self.counters.inserts.load(Ordering::Relaxed),
+            updates: self.counters.updates.load(Ordering::Relaxed),
+            deletes: self.counters.deletes.load(Ordering::Relaxed),
+            searches: self.counters.searches.load(Ordering::Relaxed),
+            peak_operation_memory: self.peak_memory.load(Ordering::Relaxed),
+        })
     }
 
-    /// Split a full internal node similarly
-    fn split_internal(&mut self, page_id: PageId) -> Result<SplitResult, BTreeError> {
-        unimplemented!()
+    /// Start a transaction: inserts, updates and deletes made through it only reach the pager
+    /// on `commit`, and are discarded if it is dropped uncommitted.
+    pub fn begin(&mut self) -> Result<Transaction<'_, P>, BTreeError> {
+        let txn = self.txn.get_mut().map_err(|_| BTreeError::LockPoisoned)?;
+        if txn.is_some() {
+            return Err(BTreeError::TransactionOpen);
+        }
+        *txn = Some(TxnState {
+            root_at_begin: self.root_page,
+            pages: HashMap::new(),
+            allocated: vec![],
+            freed: vec![],
+            counters: OpCounters::default(),
+            changed_keys: BTreeSet::new(),
+        });
+        Ok(Transaction { engine: self })
     }
 
-    /// Load a node into memory for in-page decoding and manipulation
-    fn load_node(&self, page_id: PageId) -> Result<Node, BTreeError> {
-        unimplemented!()
+    /// Page id of the current root node. Changes whenever the root splits or is swapped out.
+    pub fn root_page(&self) -> PageId {
+        self.root_page
     }
 
-    /// Write an in-memory node back to its on-disk page
-    fn write_node(&self, node: Node) -> Result<(), BTreeError> {
-        unimplemented!()
+    /// Make the tree durable: write back and sync every tree page, then record the root page
+    /// id in the database header and sync again. Pages the header could still point at until
+    /// then, such as a replaced root, are freed last.
+    ///
+    /// Every root change, from splits, merges or rebuilds, goes through here, and the order
+    /// matters: a crash before the header update leaves it pointing at the old root, with any
+    /// new pages merely leaked; the header never points at pages that aren't on disk yet.
+    pub fn flush(&mut self) -> Result<(), BTreeError> {
+        let retired = std::mem::take(&mut self.retired_pages);
+        let mut page_manager = self.lock_page_manager()?;
+        page_manager.sync()?;
+        page_manager.set_schema_root_page(self.root_page)?;
+        page_manager.sync()?;
+        for page_id in retired {
+            page_manager.free_page(page_id)?;
+        }
+        Ok(())
     }
-}
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::page_manager::DiskPageManager;
-    use std::fs;
-    use tempfile::tempdir;
 
-    fn setup_btree() -> (BTreeEngine, tempfile::TempDir) {
-        let dir = tempdir().unwrap();
-        let db_path = dir.path().join("test_btree.db");
-        
-        let page_size = 4096;
-        let page_manager = Arc::new(Mutex::new(
-            DiskPageManager::open(&db_path, page_size).unwrap()
-        ));
-        
-        let order = 4; // Small order for testing
-        let btree = BTreeEngine::new(page_manager, order).unwrap();
-        
-        (btree, dir)
+    /// Record how a search for `key` navigates the tree: one `(page, node type, child index)`
+    /// entry per level. For leaves the index is the key's slot, or where it would be inserted.
+    pub fn trace_descent(&self, key: &[u8]) -> Result<Vec<(PageId, NodeType, usize)>, BTreeError> {
+        let mut trace = vec![];
+        let mut page_id = self.root_page;
+        loop {
+            match self.load_node(page_id)? {
+                Node::Internal { keys, children, .. } => {
+                    let idx = child_index(&keys, key);
+                    trace.push((page_id, NodeType::Internal, idx));
+                    page_id = children[idx];
+                }
+                Node::Leaf { keys, .. } => {
+                    let idx = keys.binary_search_by(|k| k.as_slice().cmp(key)).unwrap_or_else(|idx| idx);
+                    trace.push((page_id, NodeType::Leaf, idx));
+                    return Ok(trace);
+                }
+            }
+        }
     }
 
-    #[test]
-    fn test_insert_and_search() {
-        let (mut btree, _dir) = setup_btree();
-        
-        // Insert some key-value pairs
-        btree.insert(b"key1", b"value1").unwrap();
-        btree.insert(b"key2", b"value2").unwrap();
-        btree.insert(b"key3", b"value3").unwrap();
-        
-        // Search for existing keys
-        let result1 = btree.search(b"key1").unwrap();
+    /// Number of levels a search for `key` descends through, counting the root as 1 and the
+    /// leaf as the last level. Returns `None` if the key isn't in the tree.
+    pub fn key_depth(&self, key: &[u8]) -> Result<Option<usize>, BTreeError> {
+        let mut page_id = self.root_page;
+        let mut depth = 1;
+        loop {
+            match self.load_node(page_id)? {
+                Node::Internal { keys, children, .. } => {
+                    page_id = children[child_index(&keys, key)];
+                    depth += 1;
+                }
+                Node::Leaf { keys, .. } => {
+                    let found = keys.binary_search_by(|k| k.as_slice().cmp(key)).is_ok();
+                    return Ok(found.then_some(depth));
+                }
+            }
+        }
+    }
+
+    /// Iterate over the leaves in key order, yielding each leaf's entries as one batch.
+    /// Lets callers work a page at a time, following the physical layout.
+    pub fn iter_leaves(&self) -> LeafIter<'_, P> {
+        LeafIter {
+            engine: self,
+            next_page: None,
+            started: false,
+        }
+    }
+
+    /// Number of entries in the tree, duplicates included. Walks the whole leaf chain.
+    pub fn len(&self) -> Result<u64, BTreeError> {
+        let mut count = 0;
+        for batch in self.iter_leaves() {
+            count += batch?.entries.len() as u64;
+        }
+        Ok(count)
+    }
+
+    /// Whether the tree holds no entries. Stops at the first non-empty leaf.
+    pub fn is_empty(&self) -> Result<bool, BTreeError> {
+        for batch in self.iter_leaves() {
+            if !batch?.entries.is_empty() {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Number of pages reachable from the root, i.e. the total page usage of this tree.
+    /// Values are stored inline, so this counts internal and leaf nodes.
+    pub fn page_footprint(&self) -> Result<u64, BTreeError> {
+        Ok(self.reachable_pages(self.root_page)?.len() as u64)
+    }
+
+    /// Replace the whole contents of the tree with `pairs`, which must be in ascending key
+    /// order (equal keys are all kept, as with `insert_dup`).
+    ///
+    /// The new entries are bulk loaded under a fresh root while the old tree stays untouched.
+    /// The new root is then swapped in and flushed to the header, and only after that are the
+    /// old tree's pages freed. If building the new tree fails, the old root is kept and the
+    /// pages built so far are freed.
+    pub fn replace_all(&mut self, pairs: impl Iterator<Item = (Vec<u8>, Vec<u8>)>) -> Result<(), BTreeError> {
+        let new_root = self.build_detached(pairs, None, LeafLinking::Eager)?;
+        let old_pages = self.reachable_pages(self.root_page)?;
+        self.root_page = new_root;
+        self.retired_pages.extend(old_pages);
+        self.flush()
+    }
+
+    /// Rebuild the tree from its own leaves as `replace_all` would, streaming them into the
+    /// new tree so only a leaf at a time is held in memory. `duplicates` handles equal keys
+    /// as in `bulk_load`, with `None` keeping all of them.
+    fn rebuild(&mut self, duplicates: Option<DuplicatePolicy>) -> Result<(), BTreeError> {
+        let mut read_error = None;
+        let pairs = self
+            .iter_leaves()
+            .map_while(|batch| batch.map_err(|e| read_error = Some(e)).ok())
+            .flat_map(|batch| batch.entries);
+        let new_root = self.build_detached(pairs, duplicates, LeafLinking::Eager)?;
+        if let Some(e) = read_error {
+            let mut page_manager = self.lock_page_manager()?;
+            for page_id in self.reachable_pages(new_root)? {
+                self.free_page_locked(&mut page_manager, page_id)?;
+            }
+            return Err(e);
+        }
+
+        let old_pages = self.reachable_pages(self.root_page)?;
+        self.root_page = new_root;
+        self.retired_pages.extend(old_pages);
+        self.flush()
+    }
+
+    /// Copy every entry into a fresh tree on `dest`, which may use a different page size.
+    ///
+    /// This is the migration path for changing page sizes, which can't be done in place. The
+    /// entries are bulk loaded in leaf order, so duplicate keys are copied as they are. The new
+    /// tree keeps this tree's order and fixed value width, and its root is persisted in
+    /// `dest`'s header, which must not hold a non-empty tree yet.
+    pub fn copy_to_new_pager<Q: Pager>(&self, dest: Arc<Mutex<Q>>) -> Result<BTreeEngine<Q>, BTreeError> {
+        let mut copy = BTreeEngine::new(dest, self.order)?;
+        copy.fixed_value_width = self.fixed_value_width;
+        copy.front_coded_leaves = self.front_coded_leaves;
+        if !copy.is_empty()? {
+            return Err(BTreeError::InvalidConfig("copy_to_new_pager needs a pager without an existing tree".to_string()));
+        }
+
+        let mut read_error = None;
+        let pairs = self
+            .iter_leaves()
+            .map_while(|batch| batch.map_err(|e| read_error = Some(e)).ok())
+            .flat_map(|batch| batch.entries);
+        let new_root = copy.build_detached(pairs, None, LeafLinking::Eager)?;
+        if let Some(e) = read_error {
+            let mut page_manager = copy.lock_page_manager()?;
+            for page_id in copy.reachable_pages(new_root)? {
+                copy.free_page_locked(&mut page_manager, page_id)?;
+            }
+            return Err(e);
+        }
+
+        copy.retired_pages.push(copy.root_page);
+        copy.root_page = new_root;
+        copy.flush()?;
+        Ok(copy)
+    }
+
+    /// Iterate over the entries with keys in `[start, end)` in key order, where `None` leaves
+    /// that side unbounded. The start leaf is found by descent, then the leaf chain is followed.
+    ///
+    /// `prefetch` is how many leaves past the current one are kept read ahead, so a large scan
+    /// reads several leaves at once instead of stalling on each. Pass 0 for short ranges,
+    /// which then read one leaf at a time. Leaves past `end` are never read ahead.
+    pub fn range(&self, start: Option<&[u8]>, end: Option<&[u8]>, prefetch: usize) -> Result<RangeIter<'_, P>, BTreeError> {
+        let page_id = match start {
+            Some(start) => self.leaf_for(start)?,
+            None => self.leftmost_leaf()?,
+        };
+        let Node::Leaf { keys, values, next_leaf, .. } = self.load_node(page_id)? else {
+            return Err(BTreeError::Corruption(format!("descent ended on internal page {}", page_id)));
+        };
+        let skip = start.map_or(0, |start| keys.partition_point(|k| k.as_slice() < start));
+        let reaches_end = end.is_some_and(|end| keys.last().is_some_and(|last| last.as_slice() >= end));
+        let current: Vec<_> = keys.into_iter().zip(values).skip(skip).collect();
+
+        let mut iter = RangeIter {
+            engine: self,
+            end: end.map(<[u8]>::to_vec),
+            current: current.into_iter(),
+            ahead: VecDeque::new(),
+            prefetch,
+            next_page: next_leaf.filter(|_| !reaches_end),
+        };
+        iter.fill_ahead()?;
+        Ok(iter)
+    }
+
+    /// The entries `range` yields for `[start, end)`, collected into a `Vec` and charged
+    /// against `with_memory_limit`. Prefer `range` for ranges of unknown size.
+    pub fn collect_range(&self, start: Option<&[u8]>, end: Option<&[u8]>) -> Result<Vec<Entry>, BTreeError> {
+        let mut out = vec![];
+        let mut used = 0;
+        for entry in self.range(start, end, 0)? {
+            let (key, value) = entry?;
+            self.charge_memory(&mut used, key.len() + value.len())?;
+            out.push((key, value));
+        }
+        Ok(out)
+    }
+
+    /// Read every page a `range` over `[start, end)` would read, without returning any
+    /// entries, so a pager with a page cache holds them before a known hot query runs. The
+    /// descent loads the internal nodes and the leaf chain is followed to the last leaf
+    /// `range` would visit.
+    pub fn warm_range(&self, start: Option<&[u8]>, end: Option<&[u8]>) -> Result<(), BTreeError> {
+        let mut page_id = match start {
+            Some(start) => self.leaf_for(start)?,
+            None => self.leftmost_leaf()?,
+        };
+        loop {
+            let Node::Leaf { keys, next_leaf, .. } = self.load_node(page_id)? else {
+                return Err(BTreeError::Corruption("leaf chain points at an internal node".to_string()));
+            };
+            let reaches_end = end.is_some_and(|end| keys.last().is_some_and(|last| last.as_slice() >= end));
+            match next_leaf {
+                Some(next) if !reaches_end => page_id = next,
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    /// Largest entry whose key is `<= key`, or `None` if every key is greater
+    pub fn floor(&self, key: &[u8]) -> Result<Option<Entry>, BTreeError> {
+        // Remember the nearest subtree to the left of the descent path: if the target leaf
+        // holds nothing <= key, the floor is the last entry of that subtree.
+        let mut page_id = self.root_page;
+        let mut left_subtree = None;
+        let (keys, values) = loop {
+            match self.load_node(page_id)? {
+                Node::Internal { keys, children, .. } => {
+                    let idx = child_index(&keys, key);
+                    if idx > 0 {
+                        left_subtree = Some(children[idx - 1]);
+                    }
+                    page_id = children[idx];
+                }
+                Node::Leaf { keys, values, .. } => break (keys, values),
+            }
+        };
+
+        let idx = keys.partition_point(|k| k.as_slice() <= key);
+        if idx > 0 {
+            return Ok(Some((keys[idx - 1].clone(), values[idx - 1].clone())));
+        }
+
+        let Some(mut page_id) = left_subtree else {
+            return Ok(None);
+        };
+        loop {
+            match self.load_node(page_id)? {
+                Node::Internal { children, .. } => page_id = children[children.len() - 1],
+                Node::Leaf { mut keys, mut values, .. } => return Ok(keys.pop().zip(values.pop())),
+            }
+        }
+    }
+
+    /// Smallest entry whose key is `>= key`, or `None` if every key is smaller
+    pub fn ceiling(&self, key: &[u8]) -> Result<Option<Entry>, BTreeError> {
+        let mut page_id = self.leaf_for(key)?;
+        let mut start = true;
+        loop {
+            let Node::Leaf { keys, values, next_leaf, .. } = self.load_node(page_id)? else {
+                return Err(BTreeError::Corruption("leaf chain points at an internal node".to_string()));
+            };
+            let idx = if start { keys.partition_point(|k| k.as_slice() < key) } else { 0 };
+            if idx < keys.len() {
+                return Ok(Some((keys[idx].clone(), values[idx].clone())));
+            }
+            match next_leaf {
+                Some(next) => page_id = next,
+                None => return Ok(None),
+            }
+            start = false;
+        }
+    }
+
+    /// Up to `limit` entries with keys strictly greater than `last_key`, in key order.
+    /// Keyset pagination: pass the last key of the previous page to fetch the next one.
+    pub fn scan_after(&self, last_key: &[u8], limit: usize) -> Result<Vec<Entry>, BTreeError> {
+        let mut out = Vec::with_capacity(limit);
+        let mut used = 0;
+        let mut next_page = Some(self.leaf_for(last_key)?);
+        while let Some(page_id) = next_page {
+            if out.len() >= limit {
+                break;
+            }
+            let Node::Leaf { keys, values, next_leaf, .. } = self.load_node(page_id)? else {
+                return Err(BTreeError::Corruption("leaf chain points at an internal node".to_string()));
+            };
+            let start = keys.partition_point(|k| k.as_slice() <= last_key);
+            let take = limit - out.len();
+            for (key, value) in keys.into_iter().zip(values).skip(start).take(take) {
+                self.charge_memory(&mut used, key.len() + value.len())?;
+                out.push((key, value));
+            }
+            next_page = next_leaf;
+        }
+        Ok(out)
+    }
+
+    /// Scan the leaves for keys stored more than once, either on purpose via `insert_dup` or
+    /// because the unique-key invariant search and delete rely on was broken. Each duplicated
+    /// key is reported once, in key order.
+    pub fn find_duplicate_keys(&self) -> Result<Vec<Vec<u8>>, BTreeError> {
+        let mut duplicates: Vec<Vec<u8>> = vec![];
+        let mut used = 0;
+        let mut prev: Option<Vec<u8>> = None;
+        for batch in self.iter_leaves() {
+            for (key, _) in batch?.entries {
+                if prev.as_ref() == Some(&key) && duplicates.last() != Some(&key) {
+                    self.charge_memory(&mut used, key.len())?;
+                    duplicates.push(key.clone());
+                }
+                prev = Some(key);
+            }
+        }
+        Ok(duplicates)
+    }
+
+    /// Remove duplicate keys, keeping one entry per key according to `policy`, and return
+    /// how many entries were dropped. The tree is only rebuilt if duplicates were found.
+    pub fn dedup(&mut self, policy: DedupPolicy) -> Result<usize, BTreeError> {
+        let mut removed = 0;
+        let mut prev: Option<Vec<u8>> = None;
+        for batch in self.iter_leaves() {
+            for (key, _) in batch?.entries {
+                if prev.as_ref() == Some(&key) {
+                    removed += 1;
+                }
+                prev = Some(key);
+            }
+        }
+
+        if removed > 0 {
+            let duplicates = match policy {
+                DedupPolicy::KeepFirst => DuplicatePolicy::KeepFirst,
+                DedupPolicy::KeepLast => DuplicatePolicy::KeepLast,
+            };
+            self.rebuild(Some(duplicates))?;
+        }
+        Ok(removed)
+    }
+
+    /// Logical size of the tree's contents: the exact number of bytes `export_entries` writes.
+    /// Computed without producing any output, so callers can preallocate buffers or check
+    /// quotas before an export.
+    pub fn serialized_size(&self) -> Result<u64, BTreeError> {
+        let mut size = 0;
+        for batch in self.iter_leaves() {
+            for (key, value) in batch?.entries {
+                size += (2 * LEN_PREFIX_SIZE + key.len() + value.len()) as u64;
+            }
+        }
+        Ok(size)
+    }
+
+    /// Write every entry in key order to `w`, each as the key and then the value, both behind
+    /// a little-endian `u32` length. Unlike a page image this is independent of the page
+    /// size and order, and holds no free space.
+    pub fn export_entries<W: Write>(&self, mut w: W) -> Result<(), BTreeError> {
+        for batch in self.iter_leaves() {
+            for (key, value) in batch?.entries {
+                for bytes in [&key, &value] {
+                    w.write_all(&(bytes.len() as u32).to_le_bytes()).map_err(BTreeError::Io)?;
+                    w.write_all(bytes).map_err(BTreeError::Io)?;
+                }
+            }
+        }
+        w.flush().map_err(BTreeError::Io)
+    }
+
+    /// Write every key in ascending order to `w`, each as a little-endian `u32` length followed
+    /// by the key bytes. Values are skipped entirely, which makes this much cheaper than a full
+    /// export when only the key sets of two databases need comparing.
+    pub fn export_keys<W: Write>(&self, mut w: W) -> Result<(), BTreeError> {
+        for batch in self.iter_leaves() {
+            for (key, _) in batch?.entries {
+                w.write_all(&(key.len() as u32).to_le_bytes()).map_err(BTreeError::Io)?;
+                w.write_all(&key).map_err(BTreeError::Io)?;
+            }
+        }
+        w.flush().map_err(BTreeError::Io)
+    }
+
+    /// Write a raw image of every page reachable from the root to `w`, for fast single-tree
+    /// backups. The image starts with the page size (`u64`), order, root id and page count
+    /// (`u32` each, all little-endian), followed by each page as its `u32` id and raw bytes.
+    pub fn export_page_image<W: Write>(&self, mut w: W) -> Result<(), BTreeError> {
+        let pages = self.reachable_pages(self.root_page)?;
+        let mut page_manager = self.lock_page_manager()?;
+        let page_size = page_manager.page_size();
+
+        w.write_all(&page_size.to_le_bytes()).map_err(BTreeError::Io)?;
+        w.write_all(&(self.order as u32).to_le_bytes()).map_err(BTreeError::Io)?;
+        w.write_all(&self.root_page.to_le_bytes()).map_err(BTreeError::Io)?;
+        w.write_all(&(pages.len() as u32).to_le_bytes()).map_err(BTreeError::Io)?;
+
+        let mut buf = vec![0u8; page_size as usize];
+        for page_id in pages {
+            page_manager.read_page(page_id, &mut buf)?;
+            w.write_all(&page_id.to_le_bytes()).map_err(BTreeError::Io)?;
+            w.write_all(&buf).map_err(BTreeError::Io)?;
+        }
+        w.flush().map_err(BTreeError::Io)
+    }
+
+    /// Restore a tree written by `export_page_image` into freshly allocated pages of
+    /// `page_manager`, rewriting child and next-leaf pointers to the new page ids. The
+    /// destination must use the same page size as the source. The restored root is not
+    /// persisted in the header; call `flush` if it should become the schema root.
+    ///
+    /// The image header is validated and every page read before anything is allocated, and
+    /// the allocated pages are freed again if restoring them fails.
+    pub fn import_page_image<R: Read>(page_manager: Arc<Mutex<P>>, mut r: R) -> Result<Self, BTreeError> {
+        let mut u64_buf = [0u8; 8];
+        let mut u32_buf = [0u8; 4];
+        r.read_exact(&mut u64_buf).map_err(BTreeError::Io)?;
+        let page_size = u64::from_le_bytes(u64_buf);
+        r.read_exact(&mut u32_buf).map_err(BTreeError::Io)?;
+        let order = u32::from_le_bytes(u32_buf) as usize;
+        r.read_exact(&mut u32_buf).map_err(BTreeError::Io)?;
+        let old_root = u32::from_le_bytes(u32_buf);
+        r.read_exact(&mut u32_buf).map_err(BTreeError::Io)?;
+        let page_count = u32::from_le_bytes(u32_buf);
+
+        {
+            let page_manager = page_manager.lock().map_err(|_| BTreeError::LockPoisoned)?;
+            if page_manager.page_size() != page_size {
+                return Err(PageManagerError::PageSizeMismatch {
+                    requested: page_size,
+                    stored: page_manager.page_size(),
+                }.into());
+            }
+        }
+        if order < 3 {
+            return Err(BTreeError::InvalidConfig(format!("page image has order {}, must be at least 3", order)));
+        }
+        check_order_fits(order, page_size)?;
+        if page_count == 0 {
+            return Err(BTreeError::Corruption("page image holds no pages".to_string()));
+        }
+
+        // The page count is untrusted, so grow with the pages actually read rather than
+        // reserving for it up front
+        let mut images = vec![];
+        let mut old_ids = HashSet::new();
+        for _ in 0..page_count {
+            r.read_exact(&mut u32_buf).map_err(BTreeError::Io)?;
+            let mut buf = vec![0u8; page_size as usize];
+            r.read_exact(&mut buf).map_err(BTreeError::Io)?;
+            let page_id = u32::from_le_bytes(u32_buf);
+            if !old_ids.insert(page_id) {
+                return Err(BTreeError::Corruption(format!("page image holds page {} twice", page_id)));
+            }
+            images.push((page_id, buf));
+        }
+        if !old_ids.contains(&old_root) {
+            return Err(BTreeError::Corruption(format!("page image doesn't contain its root page {}", old_root)));
+        }
+
+        let mut engine = BTreeEngine {
+            page_manager,
+            order,
+            root_page: 0,
+            strict_unique: false,
+            fixed_value_width: None,
+            front_coded_leaves: false,
+            memory_limit: None,
+            peak_memory: AtomicU64::new(0),
+            counters: OpCounters::default(),
+            txn: Mutex::new(None),
+            retired_pages: vec![],
+        };
+        let mut allocated = vec![];
+        if let Err(e) = engine.restore_images(images, old_root, &mut allocated) {
+            let mut page_manager = engine.lock_page_manager()?;
+            for page_id in allocated {
+                engine.free_page_locked(&mut page_manager, page_id)?;
+            }
+            return Err(e);
+        }
+        Ok(engine)
+    }
+
+    /// Write the page `images` of `import_page_image` to fresh pages, recording them in
+    /// `allocated`, and point the tree at them
+    fn restore_images(
+        &mut self,
+        images: Vec<(PageId, Vec<u8>)>,
+        old_root: PageId,
+        allocated: &mut Vec<PageId>,
+    ) -> Result<(), BTreeError> {
+        let mut remap = HashMap::new();
+        // Child pointers still hold the old ids here, so the leaf encoding is read off the images
+        self.front_coded_leaves = images.iter().any(|(_, buf)| buf[0] == PageType::FrontCodedLeaf.to_u8());
+        let fixed_value_width = {
+            let mut page_manager = self.lock_page_manager()?;
+            for (old_id, buf) in images {
+                let new_id = self.alloc_page_locked(&mut page_manager)?;
+                allocated.push(new_id);
+                page_manager.write_page(new_id, &buf)?;
+                remap.insert(old_id, new_id);
+            }
+            stored_settings(&mut *page_manager, remap[&old_root])?.1
+        };
+        self.root_page = remap[&old_root];
+        self.fixed_value_width = fixed_value_width;
+
+        let lookup = |page_id: PageId| {
+            remap.get(&page_id).copied().ok_or_else(|| {
+                BTreeError::Corruption(format!("page image references page {} it doesn't contain", page_id))
+            })
+        };
+        for &new_id in remap.values() {
+            let node = match self.load_node(new_id)? {
+                Node::Leaf { keys, values, next_leaf, .. } => Node::Leaf {
+                    page_id: new_id,
+                    keys,
+                    values,
+                    next_leaf: next_leaf.map(lookup).transpose()?,
+                },
+                Node::Internal { keys, children, .. } => Node::Internal {
+                    page_id: new_id,
+                    keys,
+                    children: children.into_iter().map(lookup).collect::<Result<_, _>>()?,
+                },
+            };
+            self.write_node(node)?;
+        }
+        Ok(())
+    }
+
+    /// Rebuild the tree with `new_order`, e.g. to move to smaller nodes. All entries are bulk
+    /// loaded under a fresh root that is then swapped in, as with `replace_all`. The new order
+    /// is recorded in every node, so reopening the tree keeps it.
+    pub fn reorganize(&mut self, new_order: usize) -> Result<(), BTreeError> {
+        if new_order < 3 {
+            return Err(BTreeError::InvalidConfig(format!("order must be at least 3, got {}", new_order)));
+        }
+        let page_size = self.lock_page_manager()?.page_size();
+        check_order_fits(new_order, page_size)?;
+
+        let old_order = self.order;
+        self.order = new_order;
+        if let Err(e) = self.rebuild(None) {
+            self.order = old_order;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Check the core B-tree invariant that every leaf sits at the same depth. Cheaper and
+    /// narrower than a full structural verification.
+    pub fn is_balanced(&self) -> Result<bool, BTreeError> {
+        let mut leaf_depth = None;
+        let mut stack = vec![(self.root_page, 0)];
+        while let Some((page_id, depth)) = stack.pop() {
+            match self.load_node(page_id)? {
+                Node::Internal { children, .. } => {
+                    stack.extend(children.into_iter().map(|child| (child, depth + 1)));
+                }
+                Node::Leaf { .. } => match leaf_depth {
+                    None => leaf_depth = Some(depth),
+                    Some(expected) if expected != depth => return Ok(false),
+                    Some(_) => {}
+                },
+            }
+        }
+        Ok(true)
+    }
+
+    /// Render the whole tree as an indented, human readable listing, one node per line.
+    /// Keys are rendered with `render`.
+    pub fn debug_dump(&self, render: KeyRender) -> Result<String, BTreeError> {
+        let mut out = String::new();
+        self.dump_node(self.root_page, 0, render, &mut out)?;
+        Ok(out)
+    }
+
+    //—— INTERNAL HELPERS —————————————————————————————————————————
+
+    /// Every page of the subtree rooted at `root`, in depth-first order
+    fn reachable_pages(&self, root: PageId) -> Result<Vec<PageId>, BTreeError> {
+        let mut pages = vec![];
+        let mut stack = vec![root];
+        while let Some(page_id) = stack.pop() {
+            pages.push(page_id);
+            if let Node::Internal { children, .. } = self.load_node(page_id)? {
+                stack.extend(children);
+            }
+        }
+        Ok(pages)
+    }
+
+    /// Descend from the root to the leaf that would hold `key`
+    fn leaf_for(&self, key: &[u8]) -> Result<PageId, BTreeError> {
+        let mut page_id = self.root_page;
+        while let Node::Internal { keys, children, .. } = self.load_node(page_id)? {
+            page_id = children[child_index(&keys, key)];
+        }
+        Ok(page_id)
+    }
+
+    /// Descend along the first child of every internal node to the leftmost leaf
+    fn leftmost_leaf(&self) -> Result<PageId, BTreeError> {
+        let mut page_id = self.root_page;
+        while let Node::Internal { children, .. } = self.load_node(page_id)? {
+            page_id = children[0];
+        }
+        Ok(page_id)
+    }
+
+    /// Append `page_id` and its subtree to the `debug_dump` output
+    fn dump_node(&self, page_id: PageId, depth: usize, render: KeyRender, out: &mut String) -> Result<(), BTreeError> {
+        let indent = "  ".repeat(depth);
+        match self.load_node(page_id)? {
+            Node::Leaf { keys, next_leaf, .. } => {
+                let keys: Vec<String> = keys.iter().map(|k| render.render(k)).collect();
+                out.push_str(&format!("{}Leaf page={} keys=[{}] next={:?}\n", indent, page_id, keys.join(", "), next_leaf));
+            }
+            Node::Internal { keys, children, .. } => {
+                let rendered: Vec<String> = keys.iter().map(|k| render.render(k)).collect();
+                out.push_str(&format!("{}Internal page={} keys=[{}]\n", indent, page_id, rendered.join(", ")));
+                for child in children {
+                    self.dump_node(child, depth + 1, render, out)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Presence check for `insert` and `update` that doesn't count as a search
+    fn key_exists(&self, key: &[u8]) -> Result<bool, BTreeError> {
+        let mut page_manager = self.lock_page_manager()?;
+        Ok(self.search_locked(&mut page_manager, key)?.is_some())
+    }
+
+    /// Reject values that don't match the configured fixed value width, if any
+    fn check_value_width(&self, value: &[u8]) -> Result<(), BTreeError> {
+        match self.fixed_value_width {
+            Some(expected) if value.len() != expected => Err(BTreeError::ValueWidthMismatch {
+                expected,
+                got: value.len(),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Add `bytes` to an operation's running total, failing once it exceeds the memory limit
+    fn charge_memory(&self, used: &mut usize, bytes: usize) -> Result<(), BTreeError> {
+        *used += bytes;
+        self.peak_memory.fetch_max(*used as u64, Ordering::Relaxed);
+        match self.memory_limit {
+            Some(limit) if *used > limit => Err(BTreeError::MemoryLimitExceeded { limit }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Insert a key/value pair, overwriting the value if the key exists. With `append`, the
+    /// pair is added after any existing entries for the key instead.
+    fn upsert(&mut self, key: &[u8], value: &[u8], append: bool) -> Result<(), BTreeError> {
+        // Remember the internal nodes on the way down so splits can walk back up
+        let mut path = vec![];
+        let mut page_id = self.root_page;
+        let (mut keys, mut values, next_leaf) = loop {
+            match self.load_node(page_id)? {
+                Node::Internal { keys, children, .. } => {
+                    path.push(page_id);
+                    page_id = children[child_index(&keys, key)];
+                }
+                Node::Leaf { keys, values, next_leaf, .. } => break (keys, values, next_leaf),
+            }
+        };
+
+        match keys.binary_search_by(|k| k.as_slice().cmp(key)) {
+            Ok(idx) if !append => values[idx] = value.to_vec(),
+            _ => {
+                let idx = keys.partition_point(|k| k.as_slice() <= key);
+                keys.insert(idx, key.to_vec());
+                values.insert(idx, value.to_vec());
+            }
+        }
+        // An overflowing node is split in memory, so only the halves ever have to fit a page
+        let overflowing = keys.len() > self.max_keys();
+        let leaf = Node::Leaf { page_id, keys, values, next_leaf };
+        if !overflowing {
+            return self.write_node(leaf);
+        }
+
+        let mut split = self.split_leaf(leaf)?;
+        while let Some(parent_id) = path.pop() {
+            let Node::Internal { mut keys, mut children, .. } = self.load_node(parent_id)? else {
+                return Err(BTreeError::Corruption(format!("page {} on the descent path is not internal", parent_id)));
+            };
+            let idx = child_index(&keys, &split.promoted_key);
+            keys.insert(idx, split.promoted_key);
+            children.insert(idx + 1, split.new_page);
+            let overflowing = keys.len() > self.max_keys();
+            let parent = Node::Internal { page_id: parent_id, keys, children };
+            if !overflowing {
+                return self.write_node(parent);
+            }
+            split = self.split_internal(parent)?;
+        }
+
+        // The root itself split: grow the tree by one level
+        let old_root = self.root_page;
+        let mut page_manager = self.lock_page_manager()?;
+        let new_root = self.alloc_page_locked(&mut page_manager)?;
+        self.write_node_locked(&mut page_manager, &Node::Internal {
+            page_id: new_root,
+            keys: vec![split.promoted_key],
+            children: vec![old_root, split.new_page],
+        })?;
+        drop(page_manager);
+        self.set_root(new_root)
+    }
+
+    /// `build_sorted` into pages nothing points at yet, returning the new root, which is an
+    /// empty leaf for empty input. On failure every page it allocated is freed again.
+    fn build_detached(
+        &self,
+        pairs: impl Iterator<Item = (Vec<u8>, Vec<u8>)>,
+        duplicates: Option<DuplicatePolicy>,
+        linking: LeafLinking,
+    ) -> Result<PageId, BTreeError> {
+        let mut allocated = vec![];
+        let built = self.build_sorted(pairs, duplicates, linking, &mut allocated).and_then(|root| match root {
+            Some(root) => Ok(root),
+            None => {
+                let page_id = self.alloc_page()?;
+                allocated.push(page_id);
+                self.write_node(Node::Leaf { page_id, keys: vec![], values: vec![], next_leaf: None })?;
+                Ok(page_id)
+            }
+        });
+        if built.is_err() {
+            let mut page_manager = self.lock_page_manager()?;
+            for page_id in allocated {
+                self.free_page_locked(&mut page_manager, page_id)?;
+            }
+        }
+        built
+    }
+
+    /// Write the sorted `pairs` into fresh leaves and internal levels above them for
+    /// `bulk_load`, returning the new root or `None` for empty input. `duplicates` handles
+    /// equal consecutive keys, with `None` keeping all of them as `insert_dup` would. Every
+    /// page allocated is pushed to `allocated` so a failed load can give them back.
+    fn build_sorted(
+        &self,
+        pairs: impl Iterator<Item = (Vec<u8>, Vec<u8>)>,
+        duplicates: Option<DuplicatePolicy>,
+        linking: LeafLinking,
+        allocated: &mut Vec<PageId>,
+    ) -> Result<Option<PageId>, BTreeError> {
+        let max_keys = self.max_keys();
+        let link = |next: PageId| (linking == LeafLinking::Eager).then_some(next);
+        // (first key, page) of every leaf written so far, the input to the level above
+        let mut level: Vec<(Vec<u8>, PageId)> = vec![];
+        // The previous leaf stays unwritten until the last one is known not to need its keys
+        let mut prev: Option<PendingLeaf> = None;
+        let mut cur: Option<PendingLeaf> = None;
+
+        for (key, value) in pairs {
+            // The current leaf always holds the previous entry, even right after a new one starts
+            if let Some(leaf) = cur.as_mut() {
+                let last = leaf.keys.last().expect("started leaves are never empty");
+                match (last.as_slice().cmp(&key), duplicates) {
+                    (std::cmp::Ordering::Less, _) | (std::cmp::Ordering::Equal, None) => {}
+                    (std::cmp::Ordering::Greater, _) => return Err(BTreeError::UnsortedInput { key }),
+                    (std::cmp::Ordering::Equal, Some(DuplicatePolicy::Error)) => {
+                        return Err(BTreeError::DuplicateInput { key });
+                    }
+                    (std::cmp::Ordering::Equal, Some(DuplicatePolicy::KeepFirst)) => continue,
+                    (std::cmp::Ordering::Equal, Some(DuplicatePolicy::KeepLast)) => {
+                        *leaf.values.last_mut().expect("started leaves are never empty") = value;
+                        continue;
+                    }
+                }
+            }
+            if cur.as_ref().is_none_or(|leaf| leaf.keys.len() == max_keys) {
+                if let (Some(leaf), Some(next)) = (prev.take(), &cur) {
+                    level.push((leaf.keys[0].clone(), leaf.page_id));
+                    self.write_node(Node::Leaf {
+                        page_id: leaf.page_id,
+                        keys: leaf.keys,
+                        values: leaf.values,
+                        next_leaf: link(next.page_id),
+                    })?;
+                }
+                let page_id = self.alloc_page()?;
+                allocated.push(page_id);
+                prev = cur.replace(PendingLeaf { page_id, keys: vec![], values: vec![] });
+            }
+            let leaf = cur.as_mut().expect("a leaf was just started");
+            leaf.keys.push(key);
+            leaf.values.push(value);
+        }
+
+        let Some(mut last) = cur else {
+            return Ok(None);
+        };
+        if let Some(mut leaf) = prev {
+            let short = self.min_keys(NodeType::Leaf).saturating_sub(last.keys.len());
+            let at = leaf.keys.len() - short;
+            last.keys.splice(0..0, leaf.keys.split_off(at));
+            last.values.splice(0..0, leaf.values.split_off(at));
+            level.push((leaf.keys[0].clone(), leaf.page_id));
+            self.write_node(Node::Leaf {
+                page_id: leaf.page_id,
+                keys: leaf.keys,
+                values: leaf.values,
+                next_leaf: link(last.page_id),
+            })?;
+        }
+        level.push((last.keys[0].clone(), last.page_id));
+        self.write_node(Node::Leaf { page_id: last.page_id, keys: last.keys, values: last.values, next_leaf: None })?;
+
+        if linking == LeafLinking::Lazy {
+            for pair in level.windows(2) {
+                let Node::Leaf { keys, values, .. } = self.load_node(pair[0].1)? else {
+                    return Err(BTreeError::Corruption(format!("bulk loaded page {} is not a leaf", pair[0].1)));
+                };
+                self.write_node(Node::Leaf { page_id: pair[0].1, keys, values, next_leaf: Some(pair[1].1) })?;
+            }
+        }
+
+        // Each internal level groups `order` children per node, evening out the last two
+        // nodes when the last one would have too few children
+        let min_children = self.min_keys(NodeType::Internal) + 1;
+        while level.len() > 1 {
+            let mut groups: Vec<Vec<(Vec<u8>, PageId)>> = vec![];
+            for chunk in level.chunks(self.order) {
+                groups.push(chunk.to_vec());
+            }
+            let last = groups.len() - 1;
+            if last > 0 && groups[last].len() < min_children {
+                let short = min_children - groups[last].len();
+                let at = groups[last - 1].len() - short;
+                let moved = groups[last - 1].split_off(at);
+                groups[last].splice(0..0, moved);
+            }
+
+            level = vec![];
+            for group in groups {
+                let page_id = self.alloc_page()?;
+                allocated.push(page_id);
+                let mut entries = group.into_iter();
+                let (first_key, first_child) = entries.next().expect("groups are never empty");
+                let (keys, mut children): (Vec<_>, Vec<_>) = entries.unzip();
+                children.insert(0, first_child);
+                self.write_node(Node::Internal { page_id, keys, children })?;
+                level.push((first_key, page_id));
+            }
+        }
+        Ok(level.pop().map(|(_, page_id)| page_id))
+    }
+
+    /// Most keys a node may hold before it has to split
+    fn max_keys(&self) -> usize {
+        self.order - 1
+    }
+
+    /// Fewest keys a non-root node may hold before `delete` rebalances it
+    fn min_keys(&self, node_type: NodeType) -> usize {
+        match node_type {
+            NodeType::Leaf => self.order / 2,
+            NodeType::Internal => self.order.div_ceil(2) - 1,
+        }
+    }
+
+    /// `min_keys` for the type of `node`
+    fn node_min_keys(&self, node: &Node) -> usize {
+        match node {
+            Node::Leaf { .. } => self.min_keys(NodeType::Leaf),
+            Node::Internal { .. } => self.min_keys(NodeType::Internal),
+        }
+    }
+
+    /// Fix up the underflowing node `page_id` by borrowing from or merging with a sibling,
+    /// walking up `path` (parent page, child index) for as long as merges underflow parents.
+    fn rebalance(&mut self, mut path: Vec<(PageId, usize)>, mut page_id: PageId) -> Result<(), BTreeError> {
+        while let Some((parent_id, idx)) = path.pop() {
+            let Node::Internal { keys: mut separators, mut children, .. } = self.load_node(parent_id)? else {
+                return Err(BTreeError::Corruption(format!("page {} on the descent path is not internal", parent_id)));
+            };
+            let mut node = self.load_node(page_id)?;
+
+            if idx > 0 {
+                let mut left = self.load_node(children[idx - 1])?;
+                if left.key_count() > self.node_min_keys(&left) {
+                    borrow_from_left(&mut left, &mut node, &mut separators[idx - 1])?;
+                    self.write_node(left)?;
+                    self.write_node(node)?;
+                    return self.write_node(Node::Internal { page_id: parent_id, keys: separators, children });
+                }
+            }
+            if idx + 1 < children.len() {
+                let mut right = self.load_node(children[idx + 1])?;
+                if right.key_count() > self.node_min_keys(&right) {
+                    borrow_from_right(&mut node, &mut right, &mut separators[idx])?;
+                    self.write_node(node)?;
+                    self.write_node(right)?;
+                    return self.write_node(Node::Internal { page_id: parent_id, keys: separators, children });
+                }
+            }
+
+            // Neither sibling can spare a key, so merge the right one of the pair into the left
+            let (mut left, right, sep_idx) = if idx > 0 {
+                (self.load_node(children[idx - 1])?, node, idx - 1)
+            } else {
+                let right = self.load_node(children[idx + 1])?;
+                (node, right, idx)
+            };
+            let separator = separators.remove(sep_idx);
+            children.remove(sep_idx + 1);
+            let right_page = right.page_id();
+            #[cfg(feature = "tracing")]
+            let demoted_key = separator.clone();
+            merge_nodes(&mut left, right, separator)?;
+            #[cfg(feature = "tracing")]
+            tracing::debug!(
+                target: "yadb::btree",
+                node = if matches!(left, Node::Leaf { .. }) { "leaf" } else { "internal" },
+                page_id = left.page_id(),
+                freed_page = right_page,
+                demoted_key = ?demoted_key,
+                keys = left.key_count(),
+                "merge",
+            );
+            self.write_node(left)?;
+            let mut page_manager = self.lock_page_manager()?;
+            self.free_page_locked(&mut page_manager, right_page)?;
+            drop(page_manager);
+
+            if path.is_empty() && separators.is_empty() {
+                // The root is down to a single child, which takes its place
+                self.retire_page(parent_id)?;
+                return self.set_root(children[0]);
+            }
+
+            let underflowing = separators.len() < self.min_keys(NodeType::Internal);
+            self.write_node(Node::Internal { page_id: parent_id, keys: separators, children })?;
+            if !underflowing || path.is_empty() {
+                return Ok(());
+            }
+            page_id = parent_id;
+        }
+        Ok(())
+    }
+
+    /// Search for a key with the page manager lock already held
+    fn search_locked(&self, page_manager: &mut P, key: &[u8]) -> Result<Option<Vec<u8>>, BTreeError> {
+        let mut page_id = self.root_page;
+        loop {
+            match self.load_node_locked(page_manager, page_id)? {
+                Node::Internal { keys, children, .. } => page_id = children[child_index(&keys, key)],
+                Node::Leaf { keys, mut values, .. } => {
+                    return Ok(keys
+                        .binary_search_by(|k| k.as_slice().cmp(key))
+                        .ok()
+                        .map(|idx| values.swap_remove(idx)));
+                }
+            }
+        }
+    }
+
+    /// Lock the shared page manager, mapping a poisoned mutex to `BTreeError::LockPoisoned`
+    fn lock_page_manager(&self) -> Result<MutexGuard<'_, P>, BTreeError> {
+        self.page_manager.lock().map_err(|_| BTreeError::LockPoisoned)
+    }
+
+    /// Lock the open transaction slot, mapping a poisoned mutex to `BTreeError::LockPoisoned`
+    fn lock_txn(&self) -> Result<MutexGuard<'_, Option<TxnState>>, BTreeError> {
+        self.txn.lock().map_err(|_| BTreeError::LockPoisoned)
+    }
+
+    /// Bump the counter `pick` selects for a successful mutation of `key`. Inside a
+    /// transaction the count is held back until commit, so rolled back work isn't reported,
+    /// and the key is remembered for `Transaction::changed_keys`.
+    fn record_mutation(&self, key: &[u8], pick: impl Fn(&OpCounters) -> &AtomicU64) -> Result<(), BTreeError> {
+        match self.lock_txn()?.as_mut() {
+            Some(txn) => {
+                txn.changed_keys.insert(key.to_vec());
+                pick(&txn.counters).fetch_add(1, Ordering::Relaxed);
+            }
+            None => {
+                pick(&self.counters).fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        Ok(())
+    }
+
+    /// `alloc_page_locked` taking the page manager lock itself
+    fn alloc_page(&self) -> Result<PageId, BTreeError> {
+        let mut page_manager = self.lock_page_manager()?;
+        self.alloc_page_locked(&mut page_manager)
+    }
+
+    /// Allocate a page for a new node, remembering it in the open transaction if any
+    fn alloc_page_locked(&self, page_manager: &mut P) -> Result<PageId, BTreeError> {
+        let page_id = page_manager.alloc_page()?;
+        if let Some(txn) = self.lock_txn()?.as_mut() {
+            txn.allocated.push(page_id);
+        }
+        Ok(page_id)
+    }
+
+    /// Free a page the tree no longer uses, deferred to commit inside a transaction
+    fn free_page_locked(&self, page_manager: &mut P, page_id: PageId) -> Result<(), BTreeError> {
+        match self.lock_txn()?.as_mut() {
+            Some(txn) => txn.freed.push(page_id),
+            None => page_manager.free_page(page_id)?,
+        }
+        Ok(())
+    }
+
+    /// Make `root` the tree's root after a split or merge. Outside a transaction the header is
+    /// moved to it right away through `flush`, so the tree on disk stays reachable even if the
+    /// engine is never flushed again; inside one, commit records it.
+    fn set_root(&mut self, root: PageId) -> Result<(), BTreeError> {
+        self.root_page = root;
+        if self.txn.get_mut().map_err(|_| BTreeError::LockPoisoned)?.is_some() {
+            return Ok(());
+        }
+        self.flush()
+    }
+
+    /// Free a page the header may still point at, e.g. a replaced root, only once `flush` has
+    /// moved the header away from it (or on commit inside a transaction)
+    fn retire_page(&mut self, page_id: PageId) -> Result<(), BTreeError> {
+        match self.txn.get_mut().map_err(|_| BTreeError::LockPoisoned)?.as_mut() {
+            Some(txn) => txn.freed.push(page_id),
+            None => self.retired_pages.push(page_id),
+        }
+        Ok(())
+    }
+
+    /// Apply the open transaction's dirty pages and root change to the pager as one batch,
+    /// then its deferred frees
+    ///
+    /// The transaction stays open until the pager accepted the batch, so a failed commit is
+    /// rolled back like a dropped transaction: the root is restored and its pages are freed.
+    fn commit_txn(&mut self) -> Result<(), BTreeError> {
+        let mut pages: Vec<_> = match self.lock_txn()?.as_mut() {
+            Some(txn) => std::mem::take(&mut txn.pages).into_iter().collect(),
+            None => return Ok(()),
+        };
+        pages.sort_unstable_by_key(|(page_id, _)| *page_id);
+        self.lock_page_manager()?.commit_pages(&pages, self.root_page)?;
+
+        let txn = self.lock_txn()?.take().expect("checked above that a transaction is open");
+        let retired = std::mem::take(&mut self.retired_pages);
+        let mut page_manager = self.lock_page_manager()?;
+        for page_id in retired.into_iter().chain(txn.freed) {
+            page_manager.free_page(page_id)?;
+        }
+        for (total, pending) in [
+            (&self.counters.inserts, &txn.counters.inserts),
+            (&self.counters.updates, &txn.counters.updates),
+            (&self.counters.deletes, &txn.counters.deletes),
+        ] {
+            total.fetch_add(pending.load(Ordering::Relaxed), Ordering::Relaxed);
+        }
+        Ok(())
+    }
+
+    /// Drop the open transaction's changes, giving back the pages it allocated
+    fn rollback_txn(&mut self) -> Result<(), BTreeError> {
+        let Some(txn) = self.txn.get_mut().map_err(|_| BTreeError::LockPoisoned)?.take() else {
+            return Ok(());
+        };
+        self.root_page = txn.root_at_begin;
+        let mut page_manager = self.lock_page_manager()?;
+        for page_id in txn.allocated {
+            page_manager.free_page(page_id)?;
+        }
+        Ok(())
+    }
+
+    /// Split an overflowing leaf held in memory and write both halves, returning new page and
+    /// key to promote.
+    ///
+    /// The upper half moves to a new leaf spliced into the chain right after this one, and the
+    /// promoted key is the new leaf's first key since leaves hold every entry.
+    fn split_leaf(&mut self, node: Node) -> Result<SplitResult, BTreeError> {
+        let Node::Leaf { page_id, mut keys, mut values, next_leaf } = node else {
+            return Err(BTreeError::Corruption(format!("split_leaf called on internal page {}", node.page_id())));
+        };
+        // Split on a boundary between runs of duplicate keys when there is one
+        let mut mid = keys.len() / 2;
+        if keys[mid - 1] == keys[mid] {
+            let run_start = keys.partition_point(|k| k < &keys[mid]);
+            let run_end = keys.partition_point(|k| k <= &keys[mid]);
+            if run_start > 0 {
+                mid = run_start;
+            } else if run_end < keys.len() {
+                mid = run_end;
+            }
+        }
+        let right_keys = keys.split_off(mid);
+        let right_values = values.split_off(mid);
+        let promoted_key = right_keys[0].clone();
+
+        let mut page_manager = self.lock_page_manager()?;
+        let new_page = self.alloc_page_locked(&mut page_manager)?;
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            target: "yadb::btree",
+            node = "leaf",
+            page_id,
+            new_page,
+            promoted_key = ?promoted_key,
+            left_keys = keys.len(),
+            right_keys = right_keys.len(),
+            "split",
+        );
+        self.write_node_locked(&mut page_manager, &Node::Leaf {
+            page_id: new_page,
+            keys: right_keys,
+            values: right_values,
+            next_leaf,
+        })?;
+        self.write_node_locked(&mut page_manager, &Node::Leaf {
+            page_id,
+            keys,
+            values,
+            next_leaf: Some(new_page),
+        })?;
+        Ok(SplitResult { new_page, promoted_key })
+    }
+
+    /// Split an overflowing internal node held in memory similarly
+    fn split_internal(&mut self, node: Node) -> Result<SplitResult, BTreeError> {
+        let Node::Internal { page_id, mut keys, mut children } = node else {
+            return Err(BTreeError::Corruption(format!("split_internal called on leaf page {}", node.page_id())));
+        };
+        // The middle separator moves up and is kept in neither half
+        let mid = keys.len() / 2;
+        let right_keys = keys.split_off(mid + 1);
+        let promoted_key = keys.pop().expect("overflowing node has a middle key");
+        let right_children = children.split_off(mid + 1);
+
+        let mut page_manager = self.lock_page_manager()?;
+        let new_page = self.alloc_page_locked(&mut page_manager)?;
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            target: "yadb::btree",
+            node = "internal",
+            page_id,
+            new_page,
+            promoted_key = ?promoted_key,
+            left_keys = keys.len(),
+            right_keys = right_keys.len(),
+            "split",
+        );
+        self.write_node_locked(&mut page_manager, &Node::Internal {
+            page_id: new_page,
+            keys: right_keys,
+            children: right_children,
+        })?;
+        self.write_node_locked(&mut page_manager, &Node::Internal { page_id, keys, children })?;
+        Ok(SplitResult { new_page, promoted_key })
+    }
+
+    /// Load a node into memory for in-page decoding and manipulation
+    fn load_node(&self, page_id: PageId) -> Result<Node, BTreeError> {
+        let mut page_manager = self.lock_page_manager()?;
+        self.load_node_locked(&mut page_manager, page_id)
+    }
+
+    /// Write an in-memory node back to its on-disk page
+    fn write_node(&self, node: Node) -> Result<(), BTreeError> {
+        let mut page_manager = self.lock_page_manager()?;
+        self.write_node_locked(&mut page_manager, &node)
+    }
+
+    /// `load_node` with the page manager lock already held.
+    ///
+    /// Node pages start with the `PageType` tag (`u8`), key count (`u32`), next-leaf page (`u64`,
+    /// 0 for none), the tree's order (`u32`) and fixed value width (`u32`, `u32::MAX` for
+    /// none), all little-endian. Leaves follow with their entries, each a length-prefixed
+    /// key and value (the value unprefixed when the tree has a fixed value width). Front-coded
+    /// leaves put a `u32` count of bytes shared with the previous key in front of every key
+    /// and prefix only the rest. Internal nodes follow with `count + 1` child page ids, then
+    /// the length-prefixed keys.
+    fn load_node_locked(&self, page_manager: &mut P, page_id: PageId) -> Result<Node, BTreeError> {
+        let dirty = self.lock_txn()?.as_ref().and_then(|txn| txn.pages.get(&page_id).cloned());
+        let mut buf = match dirty {
+            Some(buf) => buf,
+            None => {
+                let mut buf = vec![0u8; page_manager.page_size() as usize];
+                page_manager.read_page(page_id, &mut buf)?;
+                buf
+            }
+        };
+        buf.truncate(page_manager.usable_page_size() as usize);
+
+        let mut reader = NodeReader { page_id, buf: &buf, pos: 0 };
+        let tag = reader.take(1)?[0];
+        let page_type = PageType::from_u8(tag)
+            .map_err(|_| BTreeError::Corruption(format!("page {} has unknown node tag {}", page_id, tag)))?;
+        let count = reader.u32()? as usize;
+        let next_leaf = reader.u64()?;
+        reader.u32()?;
+        let width = reader.u32()?;
+        let width = (width != VARIABLE_VALUE_WIDTH).then_some(width as usize);
+
+        // Every key needs at least its length prefix, so anything larger can't be genuine
+        let max_keys = (buf.len() - NODE_HEADER_SIZE) / LEN_PREFIX_SIZE;
+        if count > max_keys {
+            return Err(BTreeError::Corruption(format!(
+                "node on page {} claims {} keys but at most {} fit", page_id, count, max_keys,
+            )));
+        }
+
+        match page_type {
+            PageType::Leaf | PageType::FrontCodedLeaf if count > 0 && width != self.fixed_value_width => {
+                Err(BTreeError::InvalidConfig(format!(
+                    "leaf on page {} was written with value width {:?} but the tree uses {:?}",
+                    page_id, width, self.fixed_value_width,
+                )))
+            }
+            PageType::Leaf | PageType::FrontCodedLeaf => {
+                let mut keys: Vec<Vec<u8>> = Vec::with_capacity(count);
+                let mut values = Vec::with_capacity(count);
+                for _ in 0..count {
+                    let key = if page_type == PageType::FrontCodedLeaf {
+                        let shared = reader.u32()? as usize;
+                        let previous = keys.last().map_or(&[][..], Vec::as_slice);
+                        if shared > previous.len() {
+                            return Err(BTreeError::Corruption(format!(
+                                "leaf on page {} shares {} bytes with a {}-byte key", page_id, shared, previous.len(),
+                            )));
+                        }
+                        let mut key = previous[..shared].to_vec();
+                        key.extend_from_slice(&reader.prefixed()?);
+                        key
+                    } else {
+                        reader.prefixed()?
+                    };
+                    keys.push(key);
+                    values.push(match self.fixed_value_width {
+                        Some(width) => reader.take(width)?.to_vec(),
+                        None => reader.prefixed()?,
+                    });
+                }
+                Ok(Node::Leaf {
+                    page_id,
+                    keys,
+                    values,
+                    next_leaf: (next_leaf != 0).then_some(next_leaf as PageId),
+                })
+            }
+            PageType::Internal => {
+                let children = (0..=count).map(|_| reader.u32()).collect::<Result<Vec<_>, _>>()?;
+                let keys = (0..count).map(|_| reader.prefixed()).collect::<Result<Vec<_>, _>>()?;
+                Ok(Node::Internal { page_id, keys, children })
+            }
+            _ => Err(BTreeError::Corruption(format!("page {} holds a {:?} page, not a node", page_id, page_type))),
+        }
+    }
+
+    /// `write_node` with the page manager lock already held
+    fn write_node_locked(&self, page_manager: &mut P, node: &Node) -> Result<(), BTreeError> {
+        let mut buf = self.encode_node(node)?;
+        let page_id = node.page_id();
+        let available = page_manager.usable_page_size() as usize;
+        if buf.len() > available {
+            return Err(BTreeError::NodeOverflow { page_id, needed: buf.len(), available });
+        }
+        buf.resize(page_manager.page_size() as usize, 0);
+        match self.lock_txn()?.as_mut() {
+            Some(txn) => {
+                txn.pages.insert(page_id, buf);
+            }
+            None => page_manager.write_page(page_id, &buf)?,
+        }
+        Ok(())
+    }
+
+    /// Serialize `node` in the layout `load_node_locked` reads, without padding it to a page
+    fn encode_node(&self, node: &Node) -> Result<Vec<u8>, BTreeError> {
+        let mut buf = vec![];
+        match node {
+            Node::Leaf { keys, values, next_leaf, .. } => {
+                let page_type = if self.front_coded_leaves { PageType::FrontCodedLeaf } else { PageType::Leaf };
+                buf.push(page_type.to_u8());
+                buf.extend_from_slice(&(keys.len() as u32).to_le_bytes());
+                buf.extend_from_slice(&(next_leaf.unwrap_or(0) as u64).to_le_bytes());
+                buf.extend_from_slice(&(self.order as u32).to_le_bytes());
+                buf.extend_from_slice(&self.fixed_value_width.map_or(VARIABLE_VALUE_WIDTH, |w| w as u32).to_le_bytes());
+                let mut previous: &[u8] = &[];
+                for (key, value) in keys.iter().zip(values) {
+                    if self.front_coded_leaves {
+                        let shared = shared_prefix_len(previous, key);
+                        buf.extend_from_slice(&(shared as u32).to_le_bytes());
+                        put_prefixed(&mut buf, &key[shared..]);
+                        previous = key;
+                    } else {
+                        put_prefixed(&mut buf, key);
+                    }
+                    match self.fixed_value_width {
+                        Some(expected) if value.len() != expected => {
+                            return Err(BTreeError::ValueWidthMismatch { expected, got: value.len() });
+                        }
+                        Some(_) => buf.extend_from_slice(value),
+                        None => put_prefixed(&mut buf, value),
+                    }
+                }
+            }
+            Node::Internal { keys, children, .. } => {
+                buf.push(PageType::Internal.to_u8());
+                buf.extend_from_slice(&(keys.len() as u32).to_le_bytes());
+                buf.extend_from_slice(&0u64.to_le_bytes());
+                buf.extend_from_slice(&(self.order as u32).to_le_bytes());
+                buf.extend_from_slice(&self.fixed_value_width.map_or(VARIABLE_VALUE_WIDTH, |w| w as u32).to_le_bytes());
+                for child in children {
+                    buf.extend_from_slice(&child.to_le_bytes());
+                }
+                for key in keys {
+                    put_prefixed(&mut buf, key);
+                }
+            }
+        }
+        Ok(buf)
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::page_manager::{DiskPageManager, MemoryPageManager};
+    use std::fs;
+    use tempfile::tempdir;
+
+    fn setup_btree() -> (BTreeEngine, tempfile::TempDir) {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test_btree.db");
+        
+        let page_size = 4096;
+        let page_manager = Arc::new(Mutex::new(
+            DiskPageManager::open(&db_path, page_size).unwrap()
+        ));
+        
+        let order = 4; // Small order for testing
+        let btree = BTreeEngine::new(page_manager, order).unwrap();
+        
+        (btree, dir)
+    }
+
+    #[test]
+    fn test_insert_and_search() {
+        let (mut btree, _dir) = setup_btree();
+        
+        // Insert some key-value pairs
+        btree.insert(b"key1", b"value1").unwrap();
+        btree.insert(b"key2", b"value2").unwrap();
+        btree.insert(b"key3", b"value3").unwrap();
+        
+        // Search for existing keys
+        let result1 = btree.search(b"key1").unwrap();
         let result2 = btree.search(b"key2").unwrap();
         let result3 = btree.search(b"key3").unwrap();
         
@@ -141,99 +2456,1707 @@ mod tests {
     }
 
     #[test]
-    fn test_update_existing_key() {
+    fn test_search_empty_tree() {
+        let (btree, _dir) = setup_btree();
+        assert_eq!(btree.search(b"anything").unwrap(), None);
+        assert_eq!(btree.search(b"").unwrap(), None);
+    }
+
+    #[test]
+    fn test_insert_dup_keeps_every_value_in_order() {
+        let (mut btree, _dir) = setup_btree();
+        for i in 0..20 {
+            btree.insert(format!("key{:03}", i).as_bytes(), b"value").unwrap();
+        }
+        for i in 0..10 {
+            btree.insert_dup(b"key010", format!("row{}", i).as_bytes()).unwrap();
+        }
+
+        let mut expected = vec![b"value".to_vec()];
+        expected.extend((0..10).map(|i| format!("row{}", i).into_bytes()));
+        assert_eq!(btree.search_all(b"key010").unwrap(), expected);
+        assert_eq!(btree.search_all(b"key011").unwrap(), vec![b"value".to_vec()]);
+        assert!(btree.search_all(b"missing").unwrap().is_empty());
+        assert!(btree.is_balanced().unwrap());
+    }
+
+    #[test]
+    fn test_btree_on_memory_pager() {
+        let page_manager = Arc::new(Mutex::new(MemoryPageManager::new(4096)));
+        let mut btree = BTreeEngine::new(page_manager.clone(), 4).unwrap();
+        for i in 0..100 {
+            btree.insert(format!("key{:03}", i).as_bytes(), format!("value{:03}", i).as_bytes()).unwrap();
+        }
+        for i in (0..100).step_by(3) {
+            btree.delete(format!("key{:03}", i).as_bytes()).unwrap();
+        }
+
+        assert!(btree.is_balanced().unwrap());
+        // Root changes from splits and merges reach the header without an explicit flush
+        assert_eq!(page_manager.lock().unwrap().schema_root_page(), btree.root_page());
+        for i in 0..100 {
+            let expected = (i % 3 != 0).then(|| format!("value{:03}", i).into_bytes());
+            assert_eq!(btree.search(format!("key{:03}", i).as_bytes()).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_reopen_after_splits_without_flush() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test_no_flush.db");
+        {
+            let page_manager = Arc::new(Mutex::new(DiskPageManager::open(&db_path, 4096).unwrap()));
+            let mut btree = BTreeEngine::new(page_manager.clone(), 4).unwrap();
+            for i in 0..20 {
+                btree.insert(format!("key{:03}", i).as_bytes(), b"value").unwrap();
+            }
+            page_manager.lock().unwrap().sync().unwrap();
+        }
+
+        let page_manager = Arc::new(Mutex::new(DiskPageManager::open(&db_path, 4096).unwrap()));
+        let btree = BTreeEngine::new(page_manager, 4).unwrap();
+        assert!(btree.is_balanced().unwrap());
+        for i in 0..20 {
+            assert_eq!(btree.search(format!("key{:03}", i).as_bytes()).unwrap(), Some(b"value".to_vec()));
+        }
+    }
+
+    #[test]
+    fn test_bulk_load_sorted_keys() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test_bulk_load.db");
+        let page_manager = Arc::new(Mutex::new(DiskPageManager::open(&db_path, 4096).unwrap()));
+        let pairs = (0..10_000).map(|i| (format!("key{:05}", i).into_bytes(), format!("value{}", i).into_bytes()));
+        let btree = BTreeEngine::bulk_load(page_manager.clone(), 16, pairs, DuplicatePolicy::Error, LeafLinking::Eager).unwrap();
+
+        assert!(btree.is_balanced().unwrap());
+        assert_eq!(btree.len().unwrap(), 10_000);
+        assert_eq!(page_manager.lock().unwrap().schema_root_page(), btree.root_page());
+        for i in (0..10_000).step_by(7) {
+            assert_eq!(btree.search(format!("key{:05}", i).as_bytes()).unwrap(), Some(format!("value{}", i).into_bytes()));
+        }
+        assert_eq!(btree.search(b"key10000").unwrap(), None);
+
+        // Full leaves use fewer pages than one-at-a-time inserts, which leave them half full
+        let mut inserted = BTreeEngine::new(Arc::new(Mutex::new(MemoryPageManager::new(4096))), 16).unwrap();
+        for i in 0..10_000 {
+            inserted.insert(format!("key{:05}", i).as_bytes(), format!("value{}", i).as_bytes()).unwrap();
+        }
+        assert!(btree.page_footprint().unwrap() < inserted.page_footprint().unwrap());
+    }
+
+    #[test]
+    fn test_bulk_load_tail_and_errors() {
+        // Sizes around full leaves and internal nodes leave short last nodes to even out
+        for n in [0, 1, 3, 4, 5, 13, 14, 15, 40] {
+            let page_manager = Arc::new(Mutex::new(MemoryPageManager::new(4096)));
+            let pairs = (0..n).map(|i| (format!("key{:03}", i).into_bytes(), vec![i as u8]));
+            let mut btree = BTreeEngine::bulk_load(page_manager, 4, pairs, DuplicatePolicy::Error, LeafLinking::Eager).unwrap();
+            assert!(btree.is_balanced().unwrap());
+            assert_eq!(btree.len().unwrap(), n as u64);
+            for i in 0..n {
+                btree.delete(format!("key{:03}", i).as_bytes()).unwrap();
+                assert!(btree.is_balanced().unwrap());
+            }
+            assert!(btree.is_empty().unwrap());
+        }
+
+        let page_manager = Arc::new(Mutex::new(MemoryPageManager::new(4096)));
+        let pairs = [b"a", b"c", b"b"].into_iter().map(|k| (k.to_vec(), vec![]));
+        let result = BTreeEngine::bulk_load(page_manager, 4, pairs, DuplicatePolicy::KeepLast, LeafLinking::Eager);
+        assert!(matches!(result, Err(BTreeError::UnsortedInput { key }) if key == b"b"));
+    }
+
+    #[test]
+    fn test_bulk_load_eager_and_lazy_linking_agree() {
+        let load = |linking| {
+            let page_manager = Arc::new(Mutex::new(MemoryPageManager::new(4096)));
+            let pairs = (0..500).map(|i| (format!("key{:04}", i).into_bytes(), vec![i as u8]));
+            BTreeEngine::bulk_load(page_manager, 5, pairs, DuplicatePolicy::Error, linking).unwrap()
+        };
+        let eager = load(LeafLinking::Eager);
+        let lazy = load(LeafLinking::Lazy);
+
+        let eager_leaves: Vec<LeafBatch> = eager.iter_leaves().collect::<Result<_, _>>().unwrap();
+        let lazy_leaves: Vec<LeafBatch> = lazy.iter_leaves().collect::<Result<_, _>>().unwrap();
+        assert_eq!(eager_leaves, lazy_leaves);
+        for btree in [&eager, &lazy] {
+            assert!(btree.is_balanced().unwrap());
+            assert_eq!(btree.len().unwrap(), 500);
+            assert_eq!(btree.range(Some(b"key0100"), Some(b"key0200"), 0).unwrap().count(), 100);
+        }
+    }
+
+    #[test]
+    fn test_bulk_load_duplicate_policies() {
+        let pairs = || {
+            (0..30).flat_map(|i| {
+                let key = format!("key{:03}", i).into_bytes();
+                let copies = if i % 5 == 0 { 3 } else { 1 };
+                (0..copies).map(move |copy| (key.clone(), format!("value{}", copy).into_bytes()))
+            })
+        };
+        let load = |duplicates| {
+            let page_manager = Arc::new(Mutex::new(MemoryPageManager::new(4096)));
+            BTreeEngine::bulk_load(page_manager, 4, pairs(), duplicates, LeafLinking::Eager)
+        };
+
+        let result = load(DuplicatePolicy::Error);
+        assert!(matches!(result, Err(BTreeError::DuplicateInput { key }) if key == b"key000"));
+
+        let first = load(DuplicatePolicy::KeepFirst).unwrap();
+        let last = load(DuplicatePolicy::KeepLast).unwrap();
+        for btree in [&first, &last] {
+            assert_eq!(btree.len().unwrap(), 30);
+            assert!(btree.find_duplicate_keys().unwrap().is_empty());
+            assert!(btree.is_balanced().unwrap());
+        }
+        assert_eq!(first.search(b"key010").unwrap(), Some(b"value0".to_vec()));
+        assert_eq!(last.search(b"key010").unwrap(), Some(b"value2".to_vec()));
+        assert_eq!(last.search(b"key011").unwrap(), Some(b"value0".to_vec()));
+    }
+
+    #[test]
+    fn test_len_and_is_empty() {
+        let (mut btree, _dir) = setup_btree();
+        assert_eq!(btree.len().unwrap(), 0);
+        assert!(btree.is_empty().unwrap());
+
+        for i in 0..30 {
+            btree.insert(format!("key{:03}", i).as_bytes(), b"value").unwrap();
+        }
+        btree.insert_dup(b"key005", b"other").unwrap();
+        assert_eq!(btree.len().unwrap(), 31);
+        assert!(!btree.is_empty().unwrap());
+
+        for i in 0..30 {
+            btree.delete(format!("key{:03}", i).as_bytes()).unwrap();
+        }
+        btree.delete(b"key005").unwrap();
+        assert_eq!(btree.len().unwrap(), 0);
+        assert!(btree.is_empty().unwrap());
+    }
+
+    #[test]
+    fn test_dropped_transaction_rolls_back() {
+        let (mut btree, _dir) = setup_btree();
+        btree.insert(b"kept", b"value").unwrap();
+        let root = btree.root_page();
+
+        {
+            let mut txn = btree.begin().unwrap();
+            txn.insert(b"temp", b"value").unwrap();
+            assert_eq!(txn.search(b"temp").unwrap(), Some(b"value".to_vec()));
+        }
+        assert_eq!(btree.search(b"temp").unwrap(), None);
+
+        // A rollback after splits restores the old root and leaves the old tree untouched
+        let mut txn = btree.begin().unwrap();
+        for i in 0..50 {
+            txn.insert(format!("key{:03}", i).as_bytes(), b"value").unwrap();
+        }
+        txn.delete(b"kept").unwrap();
+        txn.rollback();
+
+        assert_eq!(btree.root_page(), root);
+        assert_eq!(btree.search(b"kept").unwrap(), Some(b"value".to_vec()));
+        assert_eq!(btree.search(b"key000").unwrap(), None);
+        assert!(btree.is_balanced().unwrap());
+    }
+
+    #[test]
+    fn test_committed_transaction_survives_reopen() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test_btree_txn.db");
+        {
+            let page_manager = Arc::new(Mutex::new(DiskPageManager::open(&db_path, 4096).unwrap()));
+            let mut btree = BTreeEngine::new(page_manager, 4).unwrap();
+            let mut txn = btree.begin().unwrap();
+            for i in 0..50 {
+                txn.insert(format!("key{:03}", i).as_bytes(), format!("value{:03}", i).as_bytes()).unwrap();
+            }
+            for i in (0..50).step_by(2) {
+                txn.delete(format!("key{:03}", i).as_bytes()).unwrap();
+            }
+            txn.commit().unwrap();
+        }
+
+        // The commit went through the write-ahead log, which is cleared once applied
+        assert_eq!(fs::metadata(crate::wal::wal_path_for(&db_path)).unwrap().len(), 0);
+        let page_manager = Arc::new(Mutex::new(DiskPageManager::open(&db_path, 4096).unwrap()));
+        let btree = BTreeEngine::new(page_manager, 4).unwrap();
+        assert!(btree.is_balanced().unwrap());
+        for i in 0..50 {
+            let expected = (i % 2 == 1).then(|| format!("value{:03}", i).into_bytes());
+            assert_eq!(btree.search(format!("key{:03}", i).as_bytes()).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_transaction_update() {
+        let (mut btree, _dir) = setup_btree();
+        btree.insert(b"key", b"old").unwrap();
+
+        let mut txn = btree.begin().unwrap();
+        txn.update(b"key", b"new").unwrap();
+        assert_eq!(txn.search(b"key").unwrap(), Some(b"new".to_vec()));
+        assert!(matches!(txn.update(b"missing", b"value"), Err(BTreeError::NotFound)));
+        txn.commit().unwrap();
+
+        assert_eq!(btree.search(b"key").unwrap(), Some(b"new".to_vec()));
+        assert_eq!(btree.report().unwrap().updates, 1);
+
+        // Rolled back updates aren't counted
+        let mut txn = btree.begin().unwrap();
+        txn.update(b"key", b"newer").unwrap();
+        txn.rollback();
+        assert_eq!(btree.search(b"key").unwrap(), Some(b"new".to_vec()));
+        assert_eq!(btree.report().unwrap().updates, 1);
+    }
+
+    #[test]
+    fn test_transaction_changed_keys() {
+        let (mut btree, _dir) = setup_btree();
+        btree.insert(b"existing", b"value").unwrap();
+        btree.insert(b"untouched", b"value").unwrap();
+
+        let mut txn = btree.begin().unwrap();
+        assert!(txn.changed_keys().unwrap().is_empty());
+        txn.insert(b"added", b"value").unwrap();
+        txn.insert(b"added", b"again").unwrap();
+        txn.delete(b"existing").unwrap();
+        txn.delete(b"never-there").unwrap();
+        assert_eq!(txn.changed_keys().unwrap(), vec![b"added".to_vec(), b"existing".to_vec()]);
+    }
+
+    /// Memory pager whose `commit_pages` always fails, as if the disk filled up mid-commit
+    struct FailingCommitPager(MemoryPageManager);
+
+    impl Pager for FailingCommitPager {
+        fn page_size(&self) -> u64 {
+            self.0.page_size()
+        }
+
+        fn usable_page_size(&self) -> u64 {
+            self.0.usable_page_size()
+        }
+
+        fn read_page(&mut self, page_id: PageId, buf: &mut [u8]) -> Result<(), PageManagerError> {
+            self.0.read_page(page_id, buf)
+        }
+
+        fn write_page(&mut self, page_id: PageId, buf: &[u8]) -> Result<(), PageManagerError> {
+            self.0.write_page(page_id, buf)
+        }
+
+        fn alloc_page(&mut self) -> Result<PageId, PageManagerError> {
+            self.0.alloc_page()
+        }
+
+        fn free_page(&mut self, page_id: PageId) -> Result<(), PageManagerError> {
+            self.0.free_page(page_id)
+        }
+
+        fn sync(&mut self) -> Result<(), PageManagerError> {
+            self.0.sync()
+        }
+
+        fn schema_root_page(&self) -> PageId {
+            self.0.schema_root_page()
+        }
+
+        fn set_schema_root_page(&mut self, page_id: PageId) -> Result<(), PageManagerError> {
+            self.0.set_schema_root_page(page_id)
+        }
+
+        fn commit_pages(&mut self, _pages: &[(PageId, Vec<u8>)], _schema_root: PageId) -> Result<(), PageManagerError> {
+            Err(std::io::Error::other("disk full").into())
+        }
+    }
+
+    #[test]
+    fn test_failed_commit_rolls_back() {
+        let page_manager = Arc::new(Mutex::new(FailingCommitPager(MemoryPageManager::new(4096))));
+        let mut btree = BTreeEngine::new(page_manager.clone(), 4).unwrap();
+        for i in 0..10 {
+            btree.insert(format!("old{:03}", i).as_bytes(), b"value").unwrap();
+        }
+        let root = btree.root_page();
+        let pages_before = page_manager.lock().unwrap().0.page_count();
+
+        let mut txn = btree.begin().unwrap();
+        for i in 0..50 {
+            txn.insert(format!("new{:03}", i).as_bytes(), b"value").unwrap();
+        }
+        txn.update(b"old001", b"changed").unwrap();
+        txn.delete(b"old002").unwrap();
+        assert!(matches!(txn.commit(), Err(BTreeError::PageManager(_))));
+
+        assert_eq!(btree.root_page(), root);
+        assert!(btree.is_balanced().unwrap());
+        for i in 0..10 {
+            assert_eq!(btree.search(format!("old{:03}", i).as_bytes()).unwrap(), Some(b"value".to_vec()));
+        }
+        assert_eq!(btree.search(b"new000").unwrap(), None);
+        let report = btree.report().unwrap();
+        assert_eq!((report.inserts, report.updates, report.deletes), (10, 0, 0));
+
+        // Every page the transaction allocated was given back
+        let mut page_manager = page_manager.lock().unwrap();
+        let pages_after = page_manager.0.page_count();
+        assert!(pages_after > pages_before);
+        for _ in pages_before..pages_after {
+            page_manager.alloc_page().unwrap();
+        }
+        assert_eq!(page_manager.0.page_count(), pages_after);
+    }
+
+    #[test]
+    fn test_update_existing_key() {
+        let (mut btree, _dir) = setup_btree();
+        
+        // Insert a key-value pair
+        btree.insert(b"key1", b"value1").unwrap();
+        
+        // Update the value for the same key
+        btree.insert(b"key1", b"updated_value").unwrap();
+        
+        // Verify the update
+        let result = btree.search(b"key1").unwrap();
+        assert_eq!(result, Some(b"updated_value".to_vec()));
+    }
+
+    #[test]
+    fn test_delete() {
+        let (mut btree, _dir) = setup_btree();
+        
+        // Insert some key-value pairs
+        btree.insert(b"key1", b"value1").unwrap();
+        btree.insert(b"key2", b"value2").unwrap();
+        
+        // Delete a key
+        btree.delete(b"key1").unwrap();
+        
+        // Verify the key is deleted
+        let result1 = btree.search(b"key1").unwrap();
+        let result2 = btree.search(b"key2").unwrap();
+        
+        assert_eq!(result1, None);
+        assert_eq!(result2, Some(b"value2".to_vec()));
+        
+        // Delete a non-existent key (should not error)
+        btree.delete(b"key3").unwrap();
+    }
+
+    /// Point `btree` at a fresh two-leaf tree, returning the (root, left, right) pages
+    fn two_leaf_tree(btree: &mut BTreeEngine, left_keys: &[&[u8]], right_keys: &[&[u8]]) -> (PageId, PageId, PageId) {
+        let (root, left, right) = {
+            let mut page_manager = btree.page_manager.lock().unwrap();
+            (page_manager.alloc_page().unwrap(), page_manager.alloc_page().unwrap(), page_manager.alloc_page().unwrap())
+        };
+        let to_vecs = |keys: &[&[u8]]| keys.iter().map(|k| k.to_vec()).collect::<Vec<_>>();
+        btree.write_node(Node::Leaf {
+            page_id: left,
+            keys: to_vecs(left_keys),
+            values: to_vecs(left_keys),
+            next_leaf: Some(right),
+        }).unwrap();
+        btree.write_node(Node::Leaf {
+            page_id: right,
+            keys: to_vecs(right_keys),
+            values: to_vecs(right_keys),
+            next_leaf: None,
+        }).unwrap();
+        btree.write_node(Node::Internal {
+            page_id: root,
+            keys: vec![right_keys[0].to_vec()],
+            children: vec![left, right],
+        }).unwrap();
+        btree.root_page = root;
+        (root, left, right)
+    }
+
+    fn leaf_keys(btree: &BTreeEngine, page_id: PageId) -> Vec<Vec<u8>> {
+        match btree.load_node(page_id).unwrap() {
+            Node::Leaf { keys, .. } => keys,
+            Node::Internal { .. } => panic!("expected a leaf"),
+        }
+    }
+
+    fn separators(btree: &BTreeEngine, page_id: PageId) -> Vec<Vec<u8>> {
+        match btree.load_node(page_id).unwrap() {
+            Node::Internal { keys, .. } => keys,
+            Node::Leaf { .. } => panic!("expected an internal node"),
+        }
+    }
+
+    #[test]
+    fn test_delete_borrows_from_left_sibling() {
+        let (mut btree, _dir) = setup_btree();
+        let (root, left, right) = two_leaf_tree(&mut btree, &[b"a", b"b", b"c"], &[b"d", b"e"]);
+
+        btree.delete(b"e").unwrap();
+
+        assert_eq!(leaf_keys(&btree, left), vec![b"a".to_vec(), b"b".to_vec()]);
+        assert_eq!(leaf_keys(&btree, right), vec![b"c".to_vec(), b"d".to_vec()]);
+        assert_eq!(separators(&btree, root), vec![b"c".to_vec()]);
+        assert_eq!(btree.search(b"c").unwrap(), Some(b"c".to_vec()));
+    }
+
+    #[test]
+    fn test_delete_borrows_from_right_sibling() {
+        let (mut btree, _dir) = setup_btree();
+        let (root, left, right) = two_leaf_tree(&mut btree, &[b"a", b"b"], &[b"c", b"d", b"e"]);
+
+        btree.delete(b"a").unwrap();
+
+        assert_eq!(leaf_keys(&btree, left), vec![b"b".to_vec(), b"c".to_vec()]);
+        assert_eq!(leaf_keys(&btree, right), vec![b"d".to_vec(), b"e".to_vec()]);
+        assert_eq!(separators(&btree, root), vec![b"d".to_vec()]);
+        assert_eq!(btree.search(b"c").unwrap(), Some(b"c".to_vec()));
+    }
+
+    #[test]
+    fn test_delete_merges_and_collapses_root() {
+        let (mut btree, _dir) = setup_btree();
+        let (_root, left, _right) = two_leaf_tree(&mut btree, &[b"a", b"b"], &[b"c", b"d"]);
+
+        btree.delete(b"d").unwrap();
+
+        assert_eq!(btree.root_page(), left);
+        assert_eq!(leaf_keys(&btree, left), vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec()]);
+        for key in [b"a", b"b", b"c"] {
+            assert_eq!(btree.search(key).unwrap(), Some(key.to_vec()));
+        }
+        assert_eq!(btree.search(b"d").unwrap(), None);
+    }
+
+    #[test]
+    fn test_delete_everything_after_splits() {
+        let (mut btree, _dir) = setup_btree();
+        for i in 0..100 {
+            btree.insert(format!("key{:03}", i).as_bytes(), b"value").unwrap();
+        }
+        for i in (0..100).step_by(2) {
+            btree.delete(format!("key{:03}", i).as_bytes()).unwrap();
+            assert!(btree.is_balanced().unwrap());
+        }
+        for i in 0..100 {
+            let expected = (i % 2 == 1).then(|| b"value".to_vec());
+            assert_eq!(btree.search(format!("key{:03}", i).as_bytes()).unwrap(), expected);
+        }
+        for i in (1..100).step_by(2) {
+            btree.delete(format!("key{:03}", i).as_bytes()).unwrap();
+        }
+        assert_eq!(btree.iter_leaves().count(), 1);
+        assert_eq!(btree.page_footprint().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_many_inserts_for_splits() {
+        let (mut btree, _dir) = setup_btree();
+        
+        // Insert enough keys to force node splits
+        for i in 0..20 {
+            let key = format!("key{:03}", i);
+            let value = format!("value{:03}", i);
+            btree.insert(key.as_bytes(), value.as_bytes()).unwrap();
+        }
+        
+        // Verify all keys can be found
+        for i in 0..20 {
+            let key = format!("key{:03}", i);
+            let expected_value = format!("value{:03}", i);
+            
+            let result = btree.search(key.as_bytes()).unwrap();
+            assert_eq!(result, Some(expected_value.into_bytes()));
+        }
+    }
+
+    #[test]
+    fn test_collapsed_root_is_freed_once_the_header_moves() {
+        let page_manager = Arc::new(Mutex::new(MemoryPageManager::new(4096)));
+        let mut btree = BTreeEngine::new(page_manager.clone(), 4).unwrap();
+        for i in 0..4 {
+            btree.insert(format!("key{}", i).as_bytes(), b"value").unwrap();
+        }
+        let old_root = btree.root_page();
+
+        // Shrinking back to a single leaf moves the header first, then frees the old root
+        for i in 0..3 {
+            btree.delete(format!("key{}", i).as_bytes()).unwrap();
+        }
+        assert_ne!(btree.root_page(), old_root);
+        assert_eq!(page_manager.lock().unwrap().schema_root_page(), btree.root_page());
+        assert_eq!(page_manager.lock().unwrap().alloc_page().unwrap(), old_root);
+    }
+
+    #[test]
+    fn test_split_nodes_larger_than_a_page() {
+        // Four of these entries don't fit a 4096-byte page, but the halves of a split do
+        let (mut btree, _dir) = setup_btree();
+        let value = vec![7u8; 1010];
+        for i in 0..40 {
+            btree.insert(format!("key{:03}", i).as_bytes(), &value).unwrap();
+        }
+
+        assert!(btree.is_balanced().unwrap());
+        for i in 0..40 {
+            assert_eq!(btree.search(format!("key{:03}", i).as_bytes()).unwrap(), Some(value.clone()));
+        }
+    }
+
+    #[test]
+    fn test_persistence() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test_persistence.db");
+        
+        let page_size = 4096;
+        let order = 4;
+        
+        // Create a B-tree and insert data
+        {
+            let page_manager = Arc::new(Mutex::new(
+                DiskPageManager::open(&db_path, page_size).unwrap()
+            ));
+            
+            let mut btree = BTreeEngine::new(page_manager, order).unwrap();
+            
+            btree.insert(b"key1", b"value1").unwrap();
+            btree.insert(b"key2", b"value2").unwrap();
+            
+            // Ensure data is flushed to disk
+            btree.page_manager.lock().unwrap().sync().unwrap();
+        }
+        
+        // Reopen the B-tree and verify data
+        {
+            let page_manager = Arc::new(Mutex::new(
+                DiskPageManager::open(&db_path, page_size).unwrap()
+            ));
+            
+            let btree = BTreeEngine::new(page_manager, order).unwrap();
+            
+            let result1 = btree.search(b"key1").unwrap();
+            let result2 = btree.search(b"key2").unwrap();
+            
+            assert_eq!(result1, Some(b"value1".to_vec()));
+            assert_eq!(result2, Some(b"value2".to_vec()));
+        }
+    }
+
+    #[test]
+    fn test_failed_replace_keeps_old_tree() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test_flush_ordering.db");
+
+        let old_root = {
+            let page_manager = Arc::new(Mutex::new(
+                DiskPageManager::open(&db_path, 4096).unwrap()
+            ));
+            let mut btree = BTreeEngine::new(page_manager.clone(), 4).unwrap();
+            for i in 0..10 {
+                btree.insert(format!("old{}", i).as_bytes(), b"value").unwrap();
+            }
+            btree.flush().unwrap();
+            let old_root = btree.root_page();
+
+            // Out of order input fails after part of the new tree was already written
+            let pairs = (0..20).chain([0]).map(|i| (format!("new{:02}", i).into_bytes(), b"value".to_vec()));
+            assert!(matches!(btree.replace_all(pairs), Err(BTreeError::UnsortedInput { .. })));
+            assert_eq!(btree.root_page(), old_root);
+
+            // Pages the failed build freed get reused without touching the old tree
+            let mut page_manager = page_manager.lock().unwrap();
+            for _ in 0..4 {
+                let page_id = page_manager.alloc_page().unwrap();
+                page_manager.write_page(page_id, &[0xAB; 4096]).unwrap();
+            }
+            page_manager.sync().unwrap();
+            old_root
+        };
+
+        let page_manager = Arc::new(Mutex::new(
+            DiskPageManager::open(&db_path, 4096).unwrap()
+        ));
+        let btree = BTreeEngine::new(page_manager, 4).unwrap();
+        assert_eq!(btree.root_page(), old_root);
+        assert!(btree.is_balanced().unwrap());
+        for i in 0..10 {
+            assert_eq!(btree.search(format!("old{}", i).as_bytes()).unwrap(), Some(b"value".to_vec()));
+        }
+        assert_eq!(btree.search(b"new19").unwrap(), None);
+    }
+
+    #[test]
+    fn test_replace_all_persists_before_freeing_old_tree() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test_replace_reuse.db");
+
+        {
+            let page_manager = Arc::new(Mutex::new(
+                DiskPageManager::open(&db_path, 4096).unwrap()
+            ));
+            let mut btree = BTreeEngine::new(page_manager.clone(), 4).unwrap();
+            for i in 0..10 {
+                btree.insert(format!("old{}", i).as_bytes(), b"value").unwrap();
+            }
+            btree.flush().unwrap();
+
+            let pairs = (0..20).map(|i| (format!("new{:02}", i).into_bytes(), b"value".to_vec()));
+            btree.replace_all(pairs).unwrap();
+
+            // Reuse the old tree's freed pages before the database is reopened
+            let mut page_manager = page_manager.lock().unwrap();
+            for _ in 0..8 {
+                let page_id = page_manager.alloc_page().unwrap();
+                page_manager.write_page(page_id, &[0xAB; 4096]).unwrap();
+            }
+            page_manager.sync().unwrap();
+        }
+
+        let page_manager = Arc::new(Mutex::new(
+            DiskPageManager::open(&db_path, 4096).unwrap()
+        ));
+        let btree = BTreeEngine::new(page_manager, 4).unwrap();
+        assert!(btree.is_balanced().unwrap());
+        for i in 0..20 {
+            assert_eq!(btree.search(format!("new{:02}", i).as_bytes()).unwrap(), Some(b"value".to_vec()));
+        }
+        assert_eq!(btree.search(b"old0").unwrap(), None);
+    }
+
+    #[test]
+    fn test_split_leaf_keeps_leaf_chain() {
+        let (mut btree, _dir) = setup_btree();
+        let (left, tail) = {
+            let mut page_manager = btree.page_manager.lock().unwrap();
+            (page_manager.alloc_page().unwrap(), page_manager.alloc_page().unwrap())
+        };
+        let keys: Vec<Vec<u8>> = (0..5).map(|i| format!("key{}", i).into_bytes()).collect();
+        btree.write_node(Node::Leaf {
+            page_id: tail,
+            keys: vec![b"tail".to_vec()],
+            values: vec![b"value".to_vec()],
+            next_leaf: None,
+        }).unwrap();
+        let split = btree.split_leaf(Node::Leaf {
+            page_id: left,
+            keys: keys.clone(),
+            values: keys.clone(),
+            next_leaf: Some(tail),
+        }).unwrap();
+        assert_eq!(split.promoted_key, b"key2".to_vec());
+
+        let mut seen = vec![];
+        let mut next_page = Some(left);
+        while let Some(page_id) = next_page {
+            let Node::Leaf { keys, next_leaf, .. } = btree.load_node(page_id).unwrap() else {
+                panic!("expected a leaf");
+            };
+            if page_id == left {
+                assert_eq!(next_leaf, Some(split.new_page));
+            }
+            seen.extend(keys);
+            next_page = next_leaf;
+        }
+
+        let mut expected = keys;
+        expected.push(b"tail".to_vec());
+        assert_eq!(seen, expected);
+    }
+
+    #[test]
+    fn test_bulk_load_root_survives_reopen() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test_persist_root.db");
+
+        let page_size = 4096;
+        let order = 4;
+
+        let root_page = {
+            let page_manager = Arc::new(Mutex::new(
+                DiskPageManager::open(&db_path, page_size).unwrap()
+            ));
+
+            let pairs = (0..20).map(|i| (format!("key{:03}", i).into_bytes(), format!("value{:03}", i).into_bytes()));
+            let btree = BTreeEngine::bulk_load(page_manager, order, pairs, DuplicatePolicy::Error, LeafLinking::Eager).unwrap();
+            btree.root_page
+        };
+
+        let page_manager = Arc::new(Mutex::new(
+            DiskPageManager::open(&db_path, page_size).unwrap()
+        ));
+        assert_eq!(page_manager.lock().unwrap().schema_root_page(), root_page);
+
+        let btree = BTreeEngine::new(page_manager, order).unwrap();
+        for i in 0..20 {
+            let key = format!("key{:03}", i);
+            let expected_value = format!("value{:03}", i);
+            assert_eq!(btree.search(key.as_bytes()).unwrap(), Some(expected_value.into_bytes()));
+        }
+    }
+
+    #[test]
+    fn test_try_search_would_block() {
+        let (mut btree, _dir) = setup_btree();
+        btree.insert(b"key1", b"value1").unwrap();
+
+        let page_manager = Arc::clone(&btree.page_manager);
+        let _guard = page_manager.lock().unwrap();
+
+        let result = btree.try_search(b"key1", Duration::from_millis(10));
+        assert!(matches!(result, Err(BTreeError::WouldBlock)));
+    }
+
+    #[test]
+    fn test_insert_raw_and_get_raw() {
+        let (mut btree, _dir) = setup_btree();
+
+        btree.insert_raw(b"raw", b"\x00\x01raw bytes").unwrap();
+        btree.insert(b"plain", b"value").unwrap();
+
+        assert_eq!(btree.get_raw(b"raw").unwrap(), Some(b"\x00\x01raw bytes".to_vec()));
+        assert_eq!(btree.search(b"raw").unwrap(), Some(b"\x00\x01raw bytes".to_vec()));
+        assert_eq!(btree.get_raw(b"plain").unwrap(), Some(b"value".to_vec()));
+        assert_eq!(btree.get_raw(b"missing").unwrap(), None);
+    }
+
+    #[test]
+    fn test_insert_raw_skips_insert_checks() {
+        let (btree, _dir) = setup_btree();
+        let mut btree = btree.with_strict_unique(true).with_fixed_value_width(4);
+        btree.insert(b"key", b"aaaa").unwrap();
+        assert!(matches!(btree.insert(b"key", b"bbbb"), Err(BTreeError::DuplicateKey)));
+
+        btree.insert_raw(b"key", b"bbbb").unwrap();
+        assert_eq!(btree.get_raw(b"key").unwrap(), Some(b"bbbb".to_vec()));
+        assert_eq!(btree.report().unwrap().searches, 0);
+
+        // The leaf encoding itself still can't hold a value of another width
+        let result = btree.insert_raw(b"key", b"toolong");
+        assert!(matches!(result, Err(BTreeError::ValueWidthMismatch { expected: 4, got: 7 })));
+        assert_eq!(btree.search(b"key").unwrap(), Some(b"bbbb".to_vec()));
+    }
+
+    #[test]
+    fn test_key_render_modes() {
+        let key = [b'k', 0xff, 0x00, b'\n'];
+
+        assert_eq!(KeyRender::Hex.render(&key), "6bff000a");
+        assert_eq!(KeyRender::Escaped.render(&key), "k\\xff\\x00\\n");
+        assert_eq!(KeyRender::Utf8Lossy.render(&key), "k\u{fffd}\0\n");
+    }
+
+    #[test]
+    fn test_debug_dump_renders_binary_keys() {
+        let (mut btree, _dir) = setup_btree();
+        btree.insert(&[0xde, 0xad], b"value").unwrap();
+
+        let dump = btree.debug_dump(KeyRender::Hex).unwrap();
+        assert!(dump.contains("keys=[dead]"));
+
+        let dump = btree.debug_dump(KeyRender::Escaped).unwrap();
+        assert!(dump.contains("keys=[\\xde\\xad]"));
+    }
+
+    #[test]
+    fn test_key_depth_matches_height() {
+        let (mut btree, _dir) = setup_btree();
+        for i in 0..50 {
+            let key = format!("key{:03}", i);
+            btree.insert(key.as_bytes(), b"value").unwrap();
+        }
+
+        // Walk the leftmost spine to measure the height
+        let mut height = 1;
+        let mut page_id = btree.root_page;
+        while let Node::Internal { children, .. } = btree.load_node(page_id).unwrap() {
+            page_id = children[0];
+            height += 1;
+        }
+        assert!(height > 1);
+
+        assert_eq!(btree.key_depth(b"key025").unwrap(), Some(height));
+        assert_eq!(btree.key_depth(b"missing").unwrap(), None);
+    }
+
+    #[test]
+    fn test_iter_leaves_covers_all_entries_in_order() {
+        let (mut btree, _dir) = setup_btree();
+
+        // Insert out of order so leaves are split in the middle of the key space
+        for i in (0..40).rev().chain(40..60) {
+            let key = format!("key{:03}", i);
+            let value = format!("value{:03}", i);
+            btree.insert(key.as_bytes(), value.as_bytes()).unwrap();
+        }
+
+        let batches: Vec<LeafBatch> = btree.iter_leaves().collect::<Result<_, _>>().unwrap();
+        assert!(batches.len() > 1);
+
+        let all: Vec<(Vec<u8>, Vec<u8>)> = batches.into_iter().flat_map(|batch| batch.entries).collect();
+        let expected: Vec<(Vec<u8>, Vec<u8>)> = (0..60)
+            .map(|i| (format!("key{:03}", i).into_bytes(), format!("value{:03}", i).into_bytes()))
+            .collect();
+        assert_eq!(all, expected);
+    }
+
+    #[test]
+    fn test_get_or() {
+        let (mut btree, _dir) = setup_btree();
+        btree.insert(b"key1", b"value1").unwrap();
+
+        assert_eq!(btree.get_or(b"key1", b"default").unwrap(), b"value1".to_vec());
+        assert_eq!(btree.get_or(b"key2", b"default").unwrap(), b"default".to_vec());
+    }
+
+    #[test]
+    fn test_multi_contains_matches_contains() {
+        let (mut btree, _dir) = setup_btree();
+        for i in (0..60).step_by(2) {
+            let key = format!("key{:03}", i);
+            btree.insert(key.as_bytes(), b"value").unwrap();
+        }
+
+        let probes: Vec<String> = [59, 0, 13, 42, 7, 58, 100, 20, 21].iter().map(|i| format!("key{:03}", i)).collect();
+        let probes: Vec<&[u8]> = probes.iter().map(|k| k.as_bytes()).collect();
+
+        let flags = btree.multi_contains(&probes).unwrap();
+        let expected: Vec<bool> = probes.iter().map(|k| btree.contains(k).unwrap()).collect();
+        assert_eq!(flags, expected);
+        assert_eq!(flags, vec![false, true, false, true, false, true, false, true, false]);
+    }
+
+    #[test]
+    fn test_page_footprint_counts_nodes() {
+        let (mut btree, _dir) = setup_btree();
+        assert_eq!(btree.page_footprint().unwrap(), 1);
+
+        for i in 0..50 {
+            let key = format!("key{:03}", i);
+            btree.insert(key.as_bytes(), b"value").unwrap();
+        }
+
+        let leaves = btree.iter_leaves().count() as u64;
+        let mut internal = 0;
+        let mut stack = vec![btree.root_page];
+        while let Some(page_id) = stack.pop() {
+            if let Node::Internal { children, .. } = btree.load_node(page_id).unwrap() {
+                internal += 1;
+                stack.extend(children);
+            }
+        }
+        assert!(internal > 0);
+        assert_eq!(btree.page_footprint().unwrap(), leaves + internal);
+    }
+
+    #[test]
+    fn test_copy_to_new_pager_with_larger_pages() {
+        let (mut btree, dir) = setup_btree();
+        for i in 0..100 {
+            let key = format!("key{:03}", i);
+            let value = format!("value{:03}", i);
+            btree.insert(key.as_bytes(), value.as_bytes()).unwrap();
+        }
+
+        btree.insert_dup(b"key050", b"value050").unwrap();
+
+        let dest_path = dir.path().join("test_copy_8192.db");
+        {
+            let dest = Arc::new(Mutex::new(DiskPageManager::open(&dest_path, 8192).unwrap()));
+            let copy = btree.copy_to_new_pager(Arc::clone(&dest)).unwrap();
+            assert_eq!(dest.lock().unwrap().page_size(), 8192);
+            assert_eq!(copy.search_all(b"key050").unwrap().len(), 2);
+        }
+
+        // The copy's root was persisted, so it survives reopening the destination
+        let dest = Arc::new(Mutex::new(DiskPageManager::open(&dest_path, 8192).unwrap()));
+        let copy = BTreeEngine::new(dest, btree.order).unwrap();
+        assert!(copy.is_balanced().unwrap());
+        for i in 0..100 {
+            let key = format!("key{:03}", i);
+            let expected_value = format!("value{:03}", i);
+            assert_eq!(copy.search(key.as_bytes()).unwrap(), Some(expected_value.into_bytes()));
+        }
+        let all: usize = copy.iter_leaves().map(|batch| batch.unwrap().entries.len()).sum();
+        assert_eq!(all, 101);
+    }
+
+    #[test]
+    fn test_export_keys_roundtrip() {
+        let (mut btree, _dir) = setup_btree();
+        for i in (0..30).rev() {
+            let key = format!("key{:03}", i);
+            btree.insert(key.as_bytes(), b"a value that is not exported").unwrap();
+        }
+
+        let mut out = Vec::new();
+        btree.export_keys(&mut out).unwrap();
+
+        let mut imported = Vec::new();
+        let mut rest = out.as_slice();
+        while !rest.is_empty() {
+            let len = u32::from_le_bytes(rest[0..4].try_into().unwrap()) as usize;
+            imported.push(rest[4..4 + len].to_vec());
+            rest = &rest[4 + len..];
+        }
+
+        let expected: Vec<Vec<u8>> = (0..30).map(|i| format!("key{:03}", i).into_bytes()).collect();
+        assert_eq!(imported, expected);
+    }
+
+    #[test]
+    fn test_replace_all() {
+        let (mut btree, _dir) = setup_btree();
+        for i in 0..30 {
+            let key = format!("old{:03}", i);
+            btree.insert(key.as_bytes(), b"old").unwrap();
+        }
+        let old_root = btree.root_page;
+
+        let pairs = (0..10).map(|i| (format!("new{:03}", i).into_bytes(), b"new".to_vec()));
+        btree.replace_all(pairs).unwrap();
+
+        assert_ne!(btree.root_page, old_root);
+        for i in 0..30 {
+            let key = format!("old{:03}", i);
+            assert_eq!(btree.search(key.as_bytes()).unwrap(), None);
+        }
+        for i in 0..10 {
+            let key = format!("new{:03}", i);
+            assert_eq!(btree.search(key.as_bytes()).unwrap(), Some(b"new".to_vec()));
+        }
+        let all: usize = btree.iter_leaves().map(|batch| batch.unwrap().entries.len()).sum();
+        assert_eq!(all, 10);
+    }
+
+    #[test]
+    fn test_warm_range_avoids_page_reads() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test_warm_range.db");
+        {
+            let page_manager = Arc::new(Mutex::new(DiskPageManager::open(&db_path, 4096).unwrap()));
+            let mut btree = BTreeEngine::new(page_manager, 4).unwrap();
+            for i in 0..200 {
+                btree.insert(format!("key{:03}", i).as_bytes(), b"value").unwrap();
+            }
+            btree.flush().unwrap();
+        }
+
+        // Reopen with a cold cache large enough for every page the range touches
+        let page_manager = Arc::new(Mutex::new(DiskPageManager::open_with_cache(&db_path, 4096, 256).unwrap()));
+        let btree = BTreeEngine::new(page_manager.clone(), 4).unwrap();
+        let (start, end) = (b"key050".as_slice(), b"key150".as_slice());
+
+        btree.warm_range(Some(start), Some(end)).unwrap();
+        let misses = page_manager.lock().unwrap().cache_stats().misses;
+        assert!(misses > 0);
+
+        let entries = btree.range(Some(start), Some(end), 0).unwrap().count();
+        assert_eq!(entries, 100);
+        assert_eq!(page_manager.lock().unwrap().cache_stats().misses, misses);
+    }
+
+    #[test]
+    fn test_floor_and_ceiling() {
+        let (mut btree, _dir) = setup_btree();
+        for i in (10..=90).step_by(10) {
+            let key = format!("key{:03}", i);
+            let value = format!("value{:03}", i);
+            btree.insert(key.as_bytes(), value.as_bytes()).unwrap();
+        }
+        let entry = |i: u32| Some((format!("key{:03}", i).into_bytes(), format!("value{:03}", i).into_bytes()));
+
+        // Present key returns itself
+        assert_eq!(btree.floor(b"key050").unwrap(), entry(50));
+        assert_eq!(btree.ceiling(b"key050").unwrap(), entry(50));
+
+        // Between two entries
+        assert_eq!(btree.floor(b"key055").unwrap(), entry(50));
+        assert_eq!(btree.ceiling(b"key055").unwrap(), entry(60));
+
+        // Beyond the extremes
+        assert_eq!(btree.floor(b"key000").unwrap(), None);
+        assert_eq!(btree.ceiling(b"key000").unwrap(), entry(10));
+        assert_eq!(btree.floor(b"key999").unwrap(), entry(90));
+        assert_eq!(btree.ceiling(b"key999").unwrap(), None);
+    }
+
+    #[test]
+    fn test_find_and_repair_duplicate_keys() {
+        let (mut btree, _dir) = setup_btree();
+        btree.insert(b"key1", b"value1").unwrap();
+        btree.insert(b"key3", b"value3").unwrap();
+        assert!(btree.find_duplicate_keys().unwrap().is_empty());
+
+        // Sneak a second "key1" into the root leaf behind the engine's back
+        let root = btree.root_page;
+        let Node::Leaf { mut keys, mut values, next_leaf, .. } = btree.load_node(root).unwrap() else {
+            panic!("root of a tiny tree should be a leaf");
+        };
+        keys.insert(1, b"key1".to_vec());
+        values.insert(1, b"value1-dup".to_vec());
+        btree.write_node(Node::Leaf { page_id: root, keys, values, next_leaf }).unwrap();
+
+        assert_eq!(btree.find_duplicate_keys().unwrap(), vec![b"key1".to_vec()]);
+
+        assert_eq!(btree.dedup(DedupPolicy::KeepLast).unwrap(), 1);
+        assert!(btree.find_duplicate_keys().unwrap().is_empty());
+        assert_eq!(btree.search(b"key1").unwrap(), Some(b"value1-dup".to_vec()));
+        assert_eq!(btree.search(b"key3").unwrap(), Some(b"value3".to_vec()));
+    }
+
+    #[test]
+    fn test_dedup_keep_first() {
+        let (mut btree, _dir) = setup_btree();
+        for i in 0..20 {
+            btree.insert(format!("key{:03}", i).as_bytes(), b"first").unwrap();
+        }
+        btree.insert_dup(b"key007", b"second").unwrap();
+        btree.insert_dup(b"key007", b"third").unwrap();
+        assert_eq!(btree.find_duplicate_keys().unwrap(), vec![b"key007".to_vec()]);
+
+        assert_eq!(btree.dedup(DedupPolicy::KeepFirst).unwrap(), 2);
+        assert!(btree.find_duplicate_keys().unwrap().is_empty());
+        assert_eq!(btree.search_all(b"key007").unwrap(), vec![b"first".to_vec()]);
+        assert_eq!(btree.len().unwrap(), 20);
+    }
+
+    #[test]
+    fn test_root_page_changes_after_root_split() {
+        let (mut btree, _dir) = setup_btree();
+        let initial_root = btree.root_page();
+
+        for i in 0..20 {
+            let key = format!("key{:03}", i);
+            btree.insert(key.as_bytes(), b"value").unwrap();
+        }
+
+        assert_ne!(btree.root_page(), initial_root);
+        assert!(matches!(btree.load_node(btree.root_page()).unwrap(), Node::Internal { .. }));
+    }
+
+    #[test]
+    fn test_range_scan() {
         let (mut btree, _dir) = setup_btree();
-        
-        // Insert a key-value pair
-        btree.insert(b"key1", b"value1").unwrap();
-        
-        // Update the value for the same key
-        btree.insert(b"key1", b"updated_value").unwrap();
-        
-        // Verify the update
-        let result = btree.search(b"key1").unwrap();
-        assert_eq!(result, Some(b"updated_value".to_vec()));
+        for i in 0..100 {
+            let key = format!("{:03}", i);
+            btree.insert(key.as_bytes(), format!("value{}", key).as_bytes()).unwrap();
+        }
+
+        let entries: Vec<_> = btree.range(Some(b"020"), Some(b"030"), 0).unwrap().map(Result::unwrap).collect();
+        let expected: Vec<_> = (20..30)
+            .map(|i| (format!("{:03}", i).into_bytes(), format!("value{:03}", i).into_bytes()))
+            .collect();
+        assert_eq!(entries, expected);
+
+        assert_eq!(btree.range(None, Some(b"005"), 0).unwrap().count(), 5);
+        assert_eq!(btree.range(Some(b"095"), None, 0).unwrap().count(), 5);
+        assert_eq!(btree.range(None, None, 0).unwrap().count(), 100);
+        assert_eq!(btree.range(Some(b"050"), Some(b"050"), 0).unwrap().count(), 0);
     }
 
     #[test]
-    fn test_delete() {
+    fn test_scan_after_paginates() {
         let (mut btree, _dir) = setup_btree();
-        
-        // Insert some key-value pairs
-        btree.insert(b"key1", b"value1").unwrap();
-        btree.insert(b"key2", b"value2").unwrap();
-        
-        // Delete a key
-        btree.delete(b"key1").unwrap();
-        
-        // Verify the key is deleted
-        let result1 = btree.search(b"key1").unwrap();
-        let result2 = btree.search(b"key2").unwrap();
-        
-        assert_eq!(result1, None);
-        assert_eq!(result2, Some(b"value2".to_vec()));
-        
-        // Delete a non-existent key (should not error)
-        btree.delete(b"key3").unwrap();
+        for i in 0..100 {
+            let key = format!("key{:03}", i);
+            let value = format!("value{:03}", i);
+            btree.insert(key.as_bytes(), value.as_bytes()).unwrap();
+        }
+
+        let mut seen = vec![];
+        let mut last_key = vec![];
+        loop {
+            let page = btree.scan_after(&last_key, 10).unwrap();
+            if page.is_empty() {
+                break;
+            }
+            assert!(page.len() <= 10);
+            last_key = page.last().unwrap().0.clone();
+            seen.extend(page);
+        }
+
+        let expected: Vec<(Vec<u8>, Vec<u8>)> = (0..100)
+            .map(|i| (format!("key{:03}", i).into_bytes(), format!("value{:03}", i).into_bytes()))
+            .collect();
+        assert_eq!(seen, expected);
     }
 
     #[test]
-    fn test_many_inserts_for_splits() {
+    fn test_memory_limit_applies_to_collecting_scans_only() {
+        let (btree, _dir) = setup_btree();
+        let mut btree = btree.with_memory_limit(256);
+        for i in 0..100 {
+            let key = format!("key{:03}", i);
+            let value = format!("value{:03}", i);
+            btree.insert(key.as_bytes(), value.as_bytes()).unwrap();
+        }
+
+        // 100 entries of 14 bytes each is well over the limit
+        let result = btree.scan_after(b"", 100);
+        assert!(matches!(result, Err(BTreeError::MemoryLimitExceeded { limit: 256 })));
+        assert_eq!(btree.scan_after(b"", 10).unwrap().len(), 10);
+
+        // Collecting a range hits the limit where iterating the same range doesn't
+        let result = btree.collect_range(Some(b"key010"), Some(b"key090"));
+        assert!(matches!(result, Err(BTreeError::MemoryLimitExceeded { limit: 256 })));
+        let entries: Vec<_> = btree.range(Some(b"key010"), Some(b"key090"), 0).unwrap().map(Result::unwrap).collect();
+        assert_eq!(entries.len(), 80);
+        assert_eq!(btree.collect_range(Some(b"key010"), Some(b"key020")).unwrap(), entries[..10]);
+
+        for _ in 0..30 {
+            btree.insert_dup(b"key050", b"0123456789").unwrap();
+        }
+        assert!(matches!(btree.search_all(b"key050"), Err(BTreeError::MemoryLimitExceeded { limit: 256 })));
+
+        let mut count = 0;
+        for batch in btree.iter_leaves() {
+            count += batch.unwrap().entries.len();
+        }
+        assert_eq!(count, 130);
+
+        // The peak never exceeds the limit by more than the entry that crossed it
+        let peak = btree.report().unwrap().peak_operation_memory;
+        assert!(peak > 256 && peak <= 256 + 14, "peak {}", peak);
+    }
+
+    #[test]
+    fn test_report_tracks_workload() {
         let (mut btree, _dir) = setup_btree();
-        
-        // Insert enough keys to force node splits
+        for i in 0..50 {
+            let key = format!("key{:03}", i);
+            btree.insert(key.as_bytes(), b"value").unwrap();
+        }
+        for i in 0..10 {
+            let key = format!("key{:03}", i);
+            btree.update(key.as_bytes(), b"changed").unwrap();
+        }
         for i in 0..20 {
+            let key = format!("key{:03}", i);
+            assert!(btree.search(key.as_bytes()).unwrap().is_some());
+        }
+        assert!(btree.update(b"missing", b"value").is_err());
+
+        let report = btree.report().unwrap();
+        assert_eq!(report.key_count, 50);
+        assert_eq!(report.inserts, 50);
+        assert_eq!(report.updates, 10);
+        assert_eq!(report.searches, 20);
+        assert_eq!(report.deletes, 0);
+        assert!(report.height > 1);
+        assert_eq!(report.page_footprint, btree.page_footprint().unwrap());
+        assert_eq!(report.cache_hit_ratio, None);
+    }
+
+    #[test]
+    fn test_node_roundtrip() {
+        let (btree, _dir) = setup_btree();
+        let (leaf_page, internal_page) = {
+            let mut page_manager = btree.page_manager.lock().unwrap();
+            (page_manager.alloc_page().unwrap(), page_manager.alloc_page().unwrap())
+        };
+
+        btree.write_node(Node::Leaf {
+            page_id: leaf_page,
+            keys: vec![b"a".to_vec(), b"b".to_vec()],
+            values: vec![b"1".to_vec(), vec![]],
+            next_leaf: Some(7),
+        }).unwrap();
+        btree.write_node(Node::Internal {
+            page_id: internal_page,
+            keys: vec![b"m".to_vec()],
+            children: vec![leaf_page, 7],
+        }).unwrap();
+
+        match btree.load_node(leaf_page).unwrap() {
+            Node::Leaf { keys, values, next_leaf, .. } => {
+                assert_eq!(keys, vec![b"a".to_vec(), b"b".to_vec()]);
+                assert_eq!(values, vec![b"1".to_vec(), vec![]]);
+                assert_eq!(next_leaf, Some(7));
+            }
+            Node::Internal { .. } => panic!("expected a leaf"),
+        }
+        match btree.load_node(internal_page).unwrap() {
+            Node::Internal { keys, children, .. } => {
+                assert_eq!(keys, vec![b"m".to_vec()]);
+                assert_eq!(children, vec![leaf_page, 7]);
+            }
+            Node::Leaf { .. } => panic!("expected an internal node"),
+        }
+    }
+
+    #[test]
+    fn test_load_node_rejects_corrupt_pages() {
+        let (btree, _dir) = setup_btree();
+        let page_id = btree.page_manager.lock().unwrap().alloc_page().unwrap();
+
+        let mut page = vec![0u8; 4096];
+        page[0] = 9;
+        btree.page_manager.lock().unwrap().write_page(page_id, &page).unwrap();
+        assert!(matches!(btree.load_node(page_id), Err(BTreeError::Corruption(_))));
+
+        page[0] = PageType::Leaf.to_u8();
+        page[1..5].copy_from_slice(&u32::MAX.to_le_bytes());
+        btree.page_manager.lock().unwrap().write_page(page_id, &page).unwrap();
+        assert!(matches!(btree.load_node(page_id), Err(BTreeError::Corruption(_))));
+    }
+
+    #[test]
+    fn test_new_rejects_order_that_cannot_fit_page() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test_huge_order.db");
+
+        let page_manager = Arc::new(Mutex::new(
+            DiskPageManager::open(&db_path, 512).unwrap()
+        ));
+
+        let result = BTreeEngine::new(page_manager, 1000);
+        assert!(matches!(result, Err(BTreeError::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn test_new_rejects_order_below_three() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test_small_order.db");
+
+        let page_manager = Arc::new(Mutex::new(
+            DiskPageManager::open(&db_path, 4096).unwrap()
+        ));
+
+        let result = BTreeEngine::new(page_manager, 2);
+        assert!(matches!(result, Err(BTreeError::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn test_new_records_and_reloads_root() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test_new_root.db");
+
+        let page_manager = Arc::new(Mutex::new(
+            DiskPageManager::open(&db_path, 4096).unwrap()
+        ));
+        let btree = BTreeEngine::new(page_manager.clone(), 4).unwrap();
+        assert_ne!(btree.root_page(), 0);
+        assert_eq!(page_manager.lock().unwrap().schema_root_page(), btree.root_page());
+
+        let reopened = BTreeEngine::new(page_manager, 4).unwrap();
+        assert_eq!(reopened.root_page(), btree.root_page());
+    }
+
+    #[test]
+    fn test_strict_unique_rejects_duplicate_insert() {
+        let (btree, _dir) = setup_btree();
+        let mut btree = btree.with_strict_unique(true);
+
+        btree.insert(b"key1", b"value1").unwrap();
+        let result = btree.insert(b"key1", b"value2");
+        assert!(matches!(result, Err(BTreeError::DuplicateKey)));
+        assert_eq!(btree.search(b"key1").unwrap(), Some(b"value1".to_vec()));
+
+        btree.update(b"key1", b"value2").unwrap();
+        assert_eq!(btree.search(b"key1").unwrap(), Some(b"value2".to_vec()));
+
+        assert!(matches!(btree.update(b"key2", b"value"), Err(BTreeError::NotFound)));
+    }
+
+    #[test]
+    fn test_page_image_roundtrip() {
+        let (mut btree, dir) = setup_btree();
+        for i in 0..50 {
             let key = format!("key{:03}", i);
             let value = format!("value{:03}", i);
             btree.insert(key.as_bytes(), value.as_bytes()).unwrap();
         }
-        
-        // Verify all keys can be found
-        for i in 0..20 {
+
+        let mut image = Vec::new();
+        btree.export_page_image(&mut image).unwrap();
+
+        let dest_path = dir.path().join("test_import.db");
+        let dest = Arc::new(Mutex::new(DiskPageManager::open(&dest_path, 4096).unwrap()));
+        // Shift page ids in the destination so the remapping actually matters
+        dest.lock().unwrap().alloc_contiguous(7).unwrap();
+
+        let imported = BTreeEngine::import_page_image(dest, image.as_slice()).unwrap();
+        assert_eq!(imported.order, btree.order);
+        assert_eq!(imported.page_footprint().unwrap(), btree.page_footprint().unwrap());
+        for i in 0..50 {
             let key = format!("key{:03}", i);
             let expected_value = format!("value{:03}", i);
-            
-            let result = btree.search(key.as_bytes()).unwrap();
-            assert_eq!(result, Some(expected_value.into_bytes()));
+            assert_eq!(imported.search(key.as_bytes()).unwrap(), Some(expected_value.into_bytes()));
         }
+        let imported_entries: Vec<LeafBatch> = imported.iter_leaves().collect::<Result<_, _>>().unwrap();
+        let original_entries: Vec<LeafBatch> = btree.iter_leaves().collect::<Result<_, _>>().unwrap();
+        assert_eq!(
+            imported_entries.into_iter().flat_map(|b| b.entries).collect::<Vec<_>>(),
+            original_entries.into_iter().flat_map(|b| b.entries).collect::<Vec<_>>(),
+        );
     }
 
     #[test]
-    fn test_persistence() {
+    fn test_is_balanced_after_random_inserts_and_deletes() {
+        let (mut btree, _dir) = setup_btree();
+
+        // Small deterministic LCG so the sequence is reproducible without extra dependencies
+        let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+        let mut next = || {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (state >> 33) % 200
+        };
+
+        for step in 0..1000 {
+            let key = format!("key{:03}", next());
+            if step % 3 == 0 {
+                btree.delete(key.as_bytes()).unwrap();
+            } else {
+                btree.insert(key.as_bytes(), b"value").unwrap();
+            }
+            if step % 50 == 0 {
+                assert!(btree.is_balanced().unwrap());
+            }
+        }
+        assert!(btree.is_balanced().unwrap());
+    }
+
+    #[test]
+    fn test_reorganize_to_smaller_order() {
         let dir = tempdir().unwrap();
-        let db_path = dir.path().join("test_persistence.db");
-        
-        let page_size = 4096;
-        let order = 4;
-        
-        // Create a B-tree and insert data
+        let db_path = dir.path().join("test_reorganize.db");
+        let page_manager = Arc::new(Mutex::new(
+            DiskPageManager::open(&db_path, 4096).unwrap()
+        ));
+
+        let mut btree = BTreeEngine::new(page_manager, 16).unwrap();
+        for i in 0..100 {
+            let key = format!("key{:03}", i);
+            let value = format!("value{:03}", i);
+            btree.insert(key.as_bytes(), value.as_bytes()).unwrap();
+        }
+        let footprint_before = btree.page_footprint().unwrap();
+
+        btree.reorganize(4).unwrap();
+
+        assert_eq!(btree.order, 4);
+        assert!(btree.page_footprint().unwrap() > footprint_before);
+        drop(btree);
+
+        // The new order is kept across a reopen, whatever order is asked for
+        let page_manager = Arc::new(Mutex::new(
+            DiskPageManager::open(&db_path, 4096).unwrap()
+        ));
+        let btree = BTreeEngine::new(page_manager, 16).unwrap();
+        assert_eq!(btree.order, 4);
+        assert!(btree.is_balanced().unwrap());
+        for i in 0..100 {
+            let key = format!("key{:03}", i);
+            let expected_value = format!("value{:03}", i);
+            assert_eq!(btree.search(key.as_bytes()).unwrap(), Some(expected_value.into_bytes()));
+        }
+    }
+
+    #[test]
+    fn test_merge_iter_across_trees() {
+        let (mut a, _dir_a) = setup_btree();
+        let (mut b, _dir_b) = setup_btree();
+        let (mut c, _dir_c) = setup_btree();
+
+        for i in 0..60 {
+            let key = format!("key{:03}", i);
+            let tree = match i % 3 {
+                0 => &mut a,
+                1 => &mut b,
+                _ => &mut c,
+            };
+            tree.insert(key.as_bytes(), format!("{}", i % 3).as_bytes()).unwrap();
+        }
+        // A key present in two trees
+        c.insert(b"key000", b"2").unwrap();
+
+        let merged: Vec<(Vec<u8>, Vec<u8>, usize)> = merge_iter(&[&a, &b, &c]).collect::<Result<_, _>>().unwrap();
+        assert_eq!(merged.len(), 61);
+        assert!(merged.windows(2).all(|w| w[0].0 <= w[1].0));
+        assert_eq!(merged[0], (b"key000".to_vec(), b"0".to_vec(), 0));
+        assert_eq!(merged[1], (b"key000".to_vec(), b"2".to_vec(), 2));
+        for (key, value, tree_index) in &merged[2..] {
+            let i: usize = std::str::from_utf8(&key[3..]).unwrap().parse().unwrap();
+            assert_eq!(*tree_index, i % 3);
+            assert_eq!(value, format!("{}", i % 3).as_bytes());
+        }
+    }
+
+    #[test]
+    fn test_trace_descent_follows_child_pointers() {
+        let (mut btree, _dir) = setup_btree();
+        for i in 0..50 {
+            let key = format!("key{:03}", i);
+            btree.insert(key.as_bytes(), b"value").unwrap();
+        }
+
+        let trace = btree.trace_descent(b"key031").unwrap();
+        assert!(trace.len() > 1);
+        assert_eq!(trace[0].0, btree.root_page);
+        assert_eq!(trace.len(), btree.key_depth(b"key031").unwrap().unwrap());
+
+        // Every step must land on the child chosen by the previous one
+        for window in trace.windows(2) {
+            let (page_id, node_type, idx) = window[0];
+            assert_eq!(node_type, NodeType::Internal);
+            let Node::Internal { children, .. } = btree.load_node(page_id).unwrap() else {
+                panic!("expected an internal node");
+            };
+            assert_eq!(children[idx], window[1].0);
+        }
+
+        let (leaf, node_type, idx) = *trace.last().unwrap();
+        assert_eq!(node_type, NodeType::Leaf);
+        let Node::Leaf { keys, .. } = btree.load_node(leaf).unwrap() else {
+            panic!("expected a leaf");
+        };
+        assert_eq!(keys[idx], b"key031".to_vec());
+    }
+
+    #[test]
+    fn test_fixed_value_width() {
+        let (btree, _dir) = setup_btree();
+        let mut btree = btree.with_fixed_value_width(8);
+
+        for i in 0..20u64 {
+            let key = format!("counter{:03}", i);
+            btree.insert(key.as_bytes(), &i.to_le_bytes()).unwrap();
+        }
+        for i in 0..20u64 {
+            let key = format!("counter{:03}", i);
+            assert_eq!(btree.search(key.as_bytes()).unwrap(), Some(i.to_le_bytes().to_vec()));
+        }
+
+        let result = btree.insert(b"counter999", &[1, 2, 3]);
+        assert!(matches!(result, Err(BTreeError::ValueWidthMismatch { expected: 8, got: 3 })));
+        assert_eq!(btree.search(b"counter999").unwrap(), None);
+
+        let result = btree.update(b"counter001", &[0u8; 9]);
+        assert!(matches!(result, Err(BTreeError::ValueWidthMismatch { expected: 8, got: 9 })));
+    }
+
+    #[test]
+    fn test_import_rejects_bad_images_without_leaking_pages() {
+        let (mut btree, _dir) = setup_btree();
+        for i in 0..50 {
+            btree.insert(format!("key{:03}", i).as_bytes(), b"value").unwrap();
+        }
+        let mut image = Vec::new();
+        btree.export_page_image(&mut image).unwrap();
+        let page_count = u32::from_le_bytes(image[16..20].try_into().unwrap());
+
+        let dest = Arc::new(Mutex::new(MemoryPageManager::new(4096)));
+        let pages_before = dest.lock().unwrap().page_count();
+
+        let mut bad_order = image.clone();
+        bad_order[8..12].copy_from_slice(&2u32.to_le_bytes());
+        let result = BTreeEngine::import_page_image(dest.clone(), bad_order.as_slice());
+        assert!(matches!(result, Err(BTreeError::InvalidConfig(_))));
+
+        let mut huge_count = image.clone();
+        huge_count[16..20].copy_from_slice(&u32::MAX.to_le_bytes());
+        let result = BTreeEngine::import_page_image(dest.clone(), huge_count.as_slice());
+        assert!(matches!(result, Err(BTreeError::Io(_))));
+        assert_eq!(dest.lock().unwrap().page_count(), pages_before);
+
+        // Point the root's first child at a page the image doesn't contain, which is only
+        // noticed after every page was allocated
+        let mut dangling = image.clone();
+        let root = u32::from_le_bytes(image[12..16].try_into().unwrap());
+        let entry = image[20..].chunks_exact(4 + 4096).position(|e| e[..4] == root.to_le_bytes()).unwrap();
+        let child = 20 + entry * (4 + 4096) + 4 + NODE_HEADER_SIZE;
+        dangling[child..child + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        let result = BTreeEngine::import_page_image(dest.clone(), dangling.as_slice());
+        assert!(matches!(result, Err(BTreeError::Corruption(_))));
+
+        // The failed import freed its pages, so a good one fits without growing the pager
+        let grown = dest.lock().unwrap().page_count();
+        BTreeEngine::import_page_image(dest.clone(), image.as_slice()).unwrap();
+        assert_eq!(dest.lock().unwrap().page_count(), grown);
+        assert_eq!(grown, pages_before + page_count as u64);
+    }
+
+    #[test]
+    fn test_fixed_value_width_is_stored() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test_fixed_width_stored.db");
         {
-            let page_manager = Arc::new(Mutex::new(
-                DiskPageManager::open(&db_path, page_size).unwrap()
-            ));
-            
-            let mut btree = BTreeEngine::new(page_manager, order).unwrap();
-            
-            btree.insert(b"key1", b"value1").unwrap();
-            btree.insert(b"key2", b"value2").unwrap();
-            
-            // Ensure data is flushed to disk
-            btree.page_manager.lock().unwrap().sync().unwrap();
+            let page_manager = Arc::new(Mutex::new(DiskPageManager::open(&db_path, 4096).unwrap()));
+            let mut btree = BTreeEngine::new(page_manager, 4).unwrap().with_fixed_value_width(8);
+            for i in 0..20u64 {
+                btree.insert(format!("counter{:03}", i).as_bytes(), &i.to_le_bytes()).unwrap();
+            }
+            btree.flush().unwrap();
         }
-        
-        // Reopen the B-tree and verify data
+
+        // Reopening without the option picks the width up from the nodes
+        let page_manager = Arc::new(Mutex::new(DiskPageManager::open(&db_path, 4096).unwrap()));
+        let btree = BTreeEngine::new(page_manager.clone(), 4).unwrap();
+        assert_eq!(btree.fixed_value_width, Some(8));
+        assert_eq!(btree.search(b"counter007").unwrap(), Some(7u64.to_le_bytes().to_vec()));
+
+        // and so does importing a page image of the tree
+        let mut image = Vec::new();
+        btree.export_page_image(&mut image).unwrap();
+        let dest = Arc::new(Mutex::new(MemoryPageManager::new(4096)));
+        let imported = BTreeEngine::import_page_image(dest, image.as_slice()).unwrap();
+        assert_eq!(imported.fixed_value_width, Some(8));
+        assert_eq!(imported.search(b"counter007").unwrap(), Some(7u64.to_le_bytes().to_vec()));
+
+        // A conflicting width is refused instead of decoding garbage
+        let btree = btree.with_fixed_value_width(4);
+        assert!(matches!(btree.search(b"counter007"), Err(BTreeError::InvalidConfig(_))));
+    }
+
+    /// Subscriber recording the fields of every event, keyed by field name
+    #[cfg(feature = "tracing")]
+    struct CapturedEvents(Arc<Mutex<Vec<HashMap<String, String>>>>);
+
+    #[cfg(feature = "tracing")]
+    impl tracing::Subscriber for CapturedEvents {
+        fn enabled(&self, _: &tracing::Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, _: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+            tracing::span::Id::from_u64(1)
+        }
+
+        fn record(&self, _: &tracing::span::Id, _: &tracing::span::Record<'_>) {}
+
+        fn record_follows_from(&self, _: &tracing::span::Id, _: &tracing::span::Id) {}
+
+        fn event(&self, event: &tracing::Event<'_>) {
+            struct Fields(HashMap<String, String>);
+            impl tracing::field::Visit for Fields {
+                fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+                    self.0.insert(field.name().to_string(), format!("{:?}", value));
+                }
+            }
+            let mut fields = Fields(HashMap::new());
+            event.record(&mut fields);
+            self.0.lock().unwrap().push(fields.0);
+        }
+
+        fn enter(&self, _: &tracing::span::Id) {}
+
+        fn exit(&self, _: &tracing::span::Id) {}
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn test_split_emits_one_tracing_event() {
+        let page_manager = Arc::new(Mutex::new(MemoryPageManager::new(4096)));
+        let mut btree = BTreeEngine::new(page_manager, 4).unwrap();
+        for i in 0..3 {
+            btree.insert(format!("key{}", i).as_bytes(), b"value").unwrap();
+        }
+        let leaf = btree.root_page();
+
+        // The fourth key overflows the root leaf
+        let events = Arc::new(Mutex::new(vec![]));
+        tracing::subscriber::with_default(CapturedEvents(events.clone()), || {
+            btree.insert(b"key3", b"value").unwrap();
+        });
+
+        let Node::Internal { children, .. } = btree.load_node(btree.root_page()).unwrap() else {
+            panic!("root didn't grow");
+        };
+        let events = events.lock().unwrap();
+        let splits: Vec<_> = events.iter().filter(|fields| fields["message"] == "split").collect();
+        assert_eq!(splits.len(), 1);
+        assert_eq!(splits[0]["node"], "\"leaf\"");
+        assert_eq!(splits[0]["page_id"], leaf.to_string());
+        assert_eq!(children, vec![leaf, splits[0]["new_page"].parse().unwrap()]);
+        assert_eq!(splits[0]["promoted_key"], format!("{:?}", b"key2".to_vec()));
+        assert_eq!(splits[0]["left_keys"], "2");
+        assert_eq!(splits[0]["right_keys"], "2");
+    }
+
+    #[test]
+    fn test_range_prefetch() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test_range_prefetch.db");
         {
-            let page_manager = Arc::new(Mutex::new(
-                DiskPageManager::open(&db_path, page_size).unwrap()
-            ));
-            
-            let btree = BTreeEngine::new(page_manager, order).unwrap();
-            
-            let result1 = btree.search(b"key1").unwrap();
-            let result2 = btree.search(b"key2").unwrap();
-            
-            assert_eq!(result1, Some(b"value1".to_vec()));
-            assert_eq!(result2, Some(b"value2".to_vec()));
+            let page_manager = Arc::new(Mutex::new(DiskPageManager::open(&db_path, 4096).unwrap()));
+            let mut btree = BTreeEngine::new(page_manager, 8).unwrap();
+            for i in 0..300 {
+                btree.insert(format!("key{:03}", i).as_bytes(), b"value").unwrap();
+            }
+        }
+
+        // Each run starts from a cold cache, so its misses are the pages it read
+        let run = |prefetch: usize| {
+            let page_manager = Arc::new(Mutex::new(DiskPageManager::open_with_cache(&db_path, 4096, 1024).unwrap()));
+            let btree = BTreeEngine::new(page_manager.clone(), 8).unwrap();
+            let before = page_manager.lock().unwrap().cache_stats().misses;
+            let mut iter = btree.range(Some(b"key050"), Some(b"key250"), prefetch).unwrap();
+            let first = iter.next().unwrap().unwrap();
+            let read_up_front = page_manager.lock().unwrap().cache_stats().misses - before;
+            let entries: Vec<_> = std::iter::once(first).chain(iter.map(Result::unwrap)).collect();
+            (entries, read_up_front)
+        };
+        let (plain, plain_reads) = run(0);
+        let (prefetched, prefetched_reads) = run(4);
+        assert_eq!(plain.len(), 200);
+        assert_eq!(prefetched, plain);
+        assert_eq!(prefetched_reads, plain_reads + 4);
+    }
+
+    #[test]
+    fn test_front_coded_leaves() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test_front_coded.db");
+        let prefix = "/var/lib/yadb/tables/customers/index/by_email/";
+        let keys: Vec<String> = (0..30).map(|i| format!("{}{:03}", prefix, i)).collect();
+        {
+            let page_manager = Arc::new(Mutex::new(DiskPageManager::open(&db_path, 4096).unwrap()));
+            let mut btree = BTreeEngine::new(page_manager, 64).unwrap().with_front_coded_leaves(true);
+            for key in &keys {
+                btree.insert(key.as_bytes(), b"v").unwrap();
+            }
+        }
+
+        // Reopening without the option decodes the leaf and keeps writing it front-coded
+        let page_manager = Arc::new(Mutex::new(DiskPageManager::open(&db_path, 4096).unwrap()));
+        let btree = BTreeEngine::new(page_manager, 64).unwrap();
+        assert!(btree.front_coded_leaves);
+        for key in &keys {
+            assert_eq!(btree.search(key.as_bytes()).unwrap(), Some(b"v".to_vec()));
+        }
+        let all: Vec<Vec<u8>> = btree.range(None, None, 0).unwrap().map(|entry| entry.unwrap().0).collect();
+        assert_eq!(all, keys.iter().map(|key| key.as_bytes().to_vec()).collect::<Vec<_>>());
+
+        let leaf = btree.load_node(btree.root_page()).unwrap();
+        let front_coded = btree.encode_node(&leaf).unwrap();
+        let full = btree.with_front_coded_leaves(false).encode_node(&leaf).unwrap();
+        assert!(front_coded.len() < full.len() / 2, "{} vs {} bytes", front_coded.len(), full.len());
+    }
+
+    #[test]
+    fn test_serialized_size() {
+        let (mut btree, _dir) = setup_btree();
+        assert_eq!(btree.serialized_size().unwrap(), 0);
+
+        for i in 0..40 {
+            let key = format!("key{:03}", i);
+            btree.insert(key.as_bytes(), "v".repeat(i).as_bytes()).unwrap();
         }
+
+        let mut export = Vec::new();
+        btree.export_entries(&mut export).unwrap();
+        let size = btree.serialized_size().unwrap();
+        assert_eq!(size, export.len() as u64);
+        assert_eq!(size, 40 * (4 + 6 + 4) + (0..40).sum::<u64>());
+
+        // The page image carries the same data in whole pages, behind a 20-byte preamble and
+        // a 4-byte id per page
+        let mut image = Vec::new();
+        btree.export_page_image(&mut image).unwrap();
+        assert_eq!(image.len() as u64, 20 + btree.page_footprint().unwrap() * (4 + 4096));
+        assert!(size < image.len() as u64);
     }
 }
@@ -7,7 +7,15 @@ pub mod pages;
 
 /// Engine module for the database, including disk and memory engines.
 pub mod page_manager;
-mod btree;
+
+/// LRU buffer pool of pages sitting in front of the page manager's file IO.
+pub mod page_cache;
+
+/// Write-ahead log and atomic batches of page writes.
+pub mod wal;
+
+/// B+tree engine storing sorted key/value pairs on top of a pager.
+pub mod btree;
 
 /// Main function for the YADB database engine.
 fn main() {
@@ -0,0 +1,161 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::page_manager::PageId;
+
+/// Counters describing how well the page cache is doing
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Reads served from the cache
+    pub hits: u64,
+    /// Reads that had to go to disk
+    pub misses: u64,
+    /// Pages dropped to make room for others
+    pub evictions: u64,
+}
+
+/// A cached copy of a page
+struct CachedPage {
+    data: Vec<u8>,
+    dirty: bool,
+    last_used: u64,
+}
+
+/// Fixed capacity LRU cache of whole pages.
+///
+/// Writes are kept as dirty pages until they are evicted or flushed by the owner, so the
+/// cache never touches the disk itself: evictions hand dirty pages back to the caller.
+pub struct PageCache {
+    capacity: usize,
+    pages: HashMap<PageId, CachedPage>,
+    /// Recency index: access tick -> page. The smallest tick is the least recently used page.
+    lru: BTreeMap<u64, PageId>,
+    tick: u64,
+    stats: CacheStats,
+}
+
+impl PageCache {
+    /// Creates an empty cache holding at most `capacity` pages (at least one).
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            pages: HashMap::new(),
+            lru: BTreeMap::new(),
+            tick: 0,
+            stats: CacheStats::default(),
+        }
+    }
+
+    /// Look up a page, counting a hit or a miss and marking it as recently used.
+    pub fn get(&mut self, page_id: PageId) -> Option<&[u8]> {
+        if !self.pages.contains_key(&page_id) {
+            self.stats.misses += 1;
+            return None;
+        }
+        self.stats.hits += 1;
+        self.touch(page_id);
+        self.pages.get(&page_id).map(|page| page.data.as_slice())
+    }
+
+    /// Whether `page_id` is cached, without affecting recency or stats.
+    pub fn contains(&self, page_id: PageId) -> bool {
+        self.pages.contains_key(&page_id)
+    }
+
+    /// Store a copy of a page. A clean insert never overrides a dirty cached copy's flag.
+    /// Returns the evicted page if making room pushed out a dirty one, which the caller must
+    /// write back.
+    pub fn insert(&mut self, page_id: PageId, data: Vec<u8>, dirty: bool) -> Option<(PageId, Vec<u8>)> {
+        if let Some(page) = self.pages.get_mut(&page_id) {
+            page.data = data;
+            page.dirty |= dirty;
+            self.touch(page_id);
+            return None;
+        }
+
+        let evicted = if self.pages.len() >= self.capacity { self.evict() } else { None };
+
+        self.tick += 1;
+        self.lru.insert(self.tick, page_id);
+        self.pages.insert(page_id, CachedPage { data, dirty, last_used: self.tick });
+        evicted
+    }
+
+    /// Take every dirty page, leaving them cached but clean.
+    pub fn take_dirty(&mut self) -> Vec<(PageId, Vec<u8>)> {
+        let mut dirty: Vec<(PageId, Vec<u8>)> = self.pages
+            .iter_mut()
+            .filter(|(_, page)| page.dirty)
+            .map(|(&page_id, page)| {
+                page.dirty = false;
+                (page_id, page.data.clone())
+            })
+            .collect();
+        dirty.sort_unstable_by_key(|(page_id, _)| *page_id);
+        dirty
+    }
+
+    /// Current hit/miss/eviction counters.
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    /// Mark `page_id` as the most recently used page
+    fn touch(&mut self, page_id: PageId) {
+        if let Some(page) = self.pages.get_mut(&page_id) {
+            self.lru.remove(&page.last_used);
+            self.tick += 1;
+            page.last_used = self.tick;
+            self.lru.insert(self.tick, page_id);
+        }
+    }
+
+    /// Drop the least recently used page, returning it if it was dirty
+    fn evict(&mut self) -> Option<(PageId, Vec<u8>)> {
+        let (_, page_id) = self.lru.pop_first()?;
+        let page = self.pages.remove(&page_id)?;
+        self.stats.evictions += 1;
+        page.dirty.then_some((page_id, page.data))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lru_eviction_order() {
+        let mut cache = PageCache::new(2);
+        cache.insert(1, vec![1], false);
+        cache.insert(2, vec![2], false);
+
+        // Touch page 1 so page 2 becomes the least recently used
+        assert_eq!(cache.get(1), Some(&[1u8][..]));
+        cache.insert(3, vec![3], false);
+
+        assert!(cache.contains(1));
+        assert!(!cache.contains(2));
+        assert!(cache.contains(3));
+        assert_eq!(cache.stats(), CacheStats { hits: 1, misses: 0, evictions: 1 });
+    }
+
+    #[test]
+    fn test_evicting_dirty_page_returns_it() {
+        let mut cache = PageCache::new(1);
+        assert_eq!(cache.insert(1, vec![1], true), None);
+        assert_eq!(cache.insert(2, vec![2], false), Some((1, vec![1])));
+        assert_eq!(cache.insert(3, vec![3], false), None);
+        assert_eq!(cache.get(1), None);
+        assert_eq!(cache.stats().misses, 1);
+    }
+
+    #[test]
+    fn test_take_dirty_cleans_pages() {
+        let mut cache = PageCache::new(4);
+        cache.insert(2, vec![2], true);
+        cache.insert(1, vec![1], true);
+        cache.insert(3, vec![3], false);
+
+        assert_eq!(cache.take_dirty(), vec![(1, vec![1]), (2, vec![2])]);
+        assert!(cache.take_dirty().is_empty());
+    }
+}
@@ -1,9 +1,14 @@
-use std::fs::File;
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
 use std::os::unix::fs::FileExt;
-use std::path::Path;
-use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::io::Read;
+use std::thread;
+use std::time::{Duration, Instant};
 
-use crate::pages::{DatabaseHeader, SerializerError};
+use crate::page_cache::{CacheStats, PageCache};
+use crate::pages::{crc32, validate_page_size, DatabaseHeader, FreeListPage, SerializerError, HEADER_SERIALIZED_LEN, MAX_PAGE_SIZE, MIN_PAGE_SIZE};
+use crate::wal::{self, WalBatch};
 
 /// On-disk page identifier
 pub type PageId = u32;
@@ -15,6 +20,51 @@ pub enum PageManagerError {
     BadPageFormat(SerializerError),
     /// Error when IO operation fails
     IoError(std::io::Error),
+    /// Error when a page buffer is not exactly `page_size` bytes long
+    BadPageLength {
+        /// Configured page size
+        expected: u64,
+        /// Length of the buffer that was passed in
+        got: u64,
+    },
+    /// Error when a page size doesn't match the one stored in the database
+    PageSizeMismatch {
+        /// Page size the caller asked for
+        requested: u64,
+        /// Page size stored in the database header
+        stored: u64,
+    },
+    /// Error when a page's stored checksum doesn't match its contents
+    ChecksumMismatch {
+        /// Page that failed verification
+        page_id: PageId,
+        /// Checksum stored in the page
+        expected: u32,
+        /// Checksum computed over the page payload
+        found: u32,
+    },
+    /// Error when the header's magic is intact but its checksum doesn't match, as left by a
+    /// crash partway through rewriting the header
+    TornHeaderWrite {
+        /// Checksum stored in the header
+        expected: u32,
+        /// Checksum computed over the header fields
+        found: u32,
+    },
+    /// Error when freeing a page that is already on the freelist
+    DoubleFree(PageId),
+    /// Error when a page id can't be used for the operation, e.g. freeing the header page
+    /// or a page past the end of the file
+    InvalidPage(PageId),
+    /// Error when a page size isn't a power of two between `MIN_PAGE_SIZE` and `MAX_PAGE_SIZE`
+    InvalidPageSize(u64),
+    /// Error when the database file doesn't exist and the open mode doesn't allow creating it
+    NotFound,
+    /// Error when the database file already exists but the open mode requires a new one
+    AlreadyExists,
+    /// Error when an argument is outside the range an operation accepts, e.g. allocating an
+    /// empty run of pages
+    InvalidArgument(String),
 }
 
 impl std::fmt::Display for PageManagerError {
@@ -41,140 +91,1188 @@ impl From<std::io::Error> for PageManagerError {
     }
 }
 
+/// Page size used for new databases when the caller doesn't pick one
+pub const DEFAULT_PAGE_SIZE: u64 = 4096;
+
+/// Bytes at the end of every page (except the header page) holding its CRC32
+const PAGE_CHECKSUM_LEN: usize = 4;
+
+/// Byte offset of `page_id` within a database file using `page_size` pages.
+/// External tools reading the file directly should use this rather than recomputing it.
+pub fn page_offset(page_id: PageId, page_size: u64) -> u64 {
+    page_id as u64 * page_size
+}
+
+/// Check the trailing CRC32 of a full page read from disk. All-zero pages have never been
+/// written and are accepted.
+fn verify_page_checksum(page_id: PageId, page: &[u8]) -> Result<(), PageManagerError> {
+    let usable = page.len() - PAGE_CHECKSUM_LEN;
+    let expected = u32::from_le_bytes(page[usable..].try_into().unwrap());
+    let found = crc32(&page[..usable]);
+    if expected != found && page.iter().any(|&b| b != 0) {
+        return Err(PageManagerError::ChecksumMismatch { page_id, expected, found });
+    }
+    Ok(())
+}
+
+/// Run a positioned IO call, retrying it for as long as it fails with `ErrorKind::Interrupted`.
+/// A signal arriving mid-syscall shouldn't fail an otherwise valid read or write.
+fn retry_interrupted<T>(mut op: impl FnMut() -> std::io::Result<T>) -> std::io::Result<T> {
+    loop {
+        match op() {
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            result => return result,
+        }
+    }
+}
+
+/// Hook notified after every page read and write, e.g. to feed an external cache or a
+/// replication stream. Register it with `DiskPageManager::set_observer`.
+pub trait PageObserver: Send {
+    /// Called after `page_id` was read into `data`
+    fn on_read(&mut self, page_id: PageId, data: &[u8]);
+    /// Called after `data` was written to `page_id`
+    fn on_write(&mut self, page_id: PageId, data: &[u8]);
+}
+
+/// Page storage a `BTreeEngine` can run on, implemented by `DiskPageManager` and
+/// `MemoryPageManager`
+pub trait Pager {
+    /// Size of each page in bytes
+    fn page_size(&self) -> u64;
+    /// Bytes of each page available to callers
+    fn usable_page_size(&self) -> u64;
+    /// Read page `page_id` into `buf` (exactly `page_size` bytes)
+    fn read_page(&mut self, page_id: PageId, buf: &mut [u8]) -> Result<(), PageManagerError>;
+    /// Write `buf` (exactly `page_size` bytes) to page `page_id`
+    fn write_page(&mut self, page_id: PageId, buf: &[u8]) -> Result<(), PageManagerError>;
+    /// Allocate a page, reusing a freed one if possible
+    fn alloc_page(&mut self) -> Result<PageId, PageManagerError>;
+    /// Return a page to the free pool
+    fn free_page(&mut self, page_id: PageId) -> Result<(), PageManagerError>;
+    /// Make every write so far durable
+    fn sync(&mut self) -> Result<(), PageManagerError>;
+    /// Root page of the schema B-tree, 0 if none has been created yet
+    fn schema_root_page(&self) -> PageId;
+    /// Record the root page of the schema B-tree
+    fn set_schema_root_page(&mut self, page_id: PageId) -> Result<(), PageManagerError>;
+    /// Page cache counters, all zero for pagers without a cache
+    fn cache_stats(&self) -> CacheStats {
+        CacheStats::default()
+    }
+    /// Durably write `pages`, then record `schema_root` as the schema root, all together or
+    /// not at all if the pager can survive a crash. By default the pages are written and
+    /// synced before the header, which is enough for pagers that don't outlive the process.
+    fn commit_pages(&mut self, pages: &[(PageId, Vec<u8>)], schema_root: PageId) -> Result<(), PageManagerError> {
+        for (page_id, buf) in pages {
+            self.write_page(*page_id, buf)?;
+        }
+        self.sync()?;
+        self.set_schema_root_page(schema_root)?;
+        self.sync()
+    }
+}
+
+/// How `DiskPageManager::open_with_mode` treats a missing or existing database file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpenMode {
+    /// Open the file, creating a new database if it doesn't exist
+    CreateIfMissing,
+    /// Only open an existing database, failing with `PageManagerError::NotFound` otherwise
+    OpenExisting,
+    /// Only create a new database, failing with `PageManagerError::AlreadyExists` if the file exists
+    CreateNew,
+}
+
+/// How hard `DiskPageManager` works to keep the file consistent across a crash when it grows
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SyncMode {
+    /// Extend the file and rewrite the header without waiting for either to reach the disk.
+    /// `open` still adopts pages a crash left unrecorded.
+    #[default]
+    Normal,
+    /// Write and sync new page bodies before the header records them, then sync the header,
+    /// so the header never counts a page the file doesn't have
+    Full,
+}
+
+/// Faults tests can inject into the write path
+#[cfg(test)]
+#[derive(Default)]
+struct FaultInjection {
+    /// Fail after new pages are synced but before the header records them
+    crash_before_header: bool,
+    /// Report page writes as done without writing anything, like a disk that loses them
+    drop_writes: bool,
+}
+
+/// Page that holds the header copy of databases created with `DiskPageManagerBuilder::header_backup`
+const HEADER_BACKUP_PAGE: PageId = 1;
+
+/// Snapshot of how fragmented the page space is, as returned by `DiskPageManager::fragmentation`
+#[derive(Debug, Clone, PartialEq)]
+pub struct FragReport {
+    /// Total number of pages in the file, including the header page
+    pub total_pages: u64,
+    /// Number of pages on the freelist
+    pub free_pages: u64,
+    /// `free_pages / total_pages`
+    pub free_ratio: f64,
+    /// Length of the longest run of physically contiguous free pages
+    pub largest_free_run: u64,
+}
+
+/// Builder collecting `DiskPageManager` options, finished with `open`.
+/// `DiskPageManager::open` is the shortcut for the defaults.
+#[derive(Debug, Clone)]
+pub struct DiskPageManagerBuilder {
+    page_size: Option<u64>,
+    mode: OpenMode,
+    write_budget: Option<u64>,
+    cache_capacity: Option<usize>,
+    app_id: Option<[u8; 4]>,
+    sync_mode: SyncMode,
+    compact_freelist_on_open: bool,
+    header_backup: bool,
+    verify_after_sync: bool,
+    checkpoint_on_close: bool,
+}
+
+impl Default for DiskPageManagerBuilder {
+    fn default() -> Self {
+        Self {
+            page_size: None,
+            mode: OpenMode::CreateIfMissing,
+            write_budget: None,
+            cache_capacity: None,
+            app_id: None,
+            sync_mode: SyncMode::Normal,
+            compact_freelist_on_open: false,
+            header_backup: false,
+            verify_after_sync: false,
+            checkpoint_on_close: false,
+        }
+    }
+}
+
+impl DiskPageManagerBuilder {
+    /// Page size of the database. New databases default to `DEFAULT_PAGE_SIZE`; if left unset,
+    /// existing ones are opened with whatever page size they store, otherwise it must match.
+    pub fn page_size(mut self, page_size: u64) -> Self {
+        self.page_size = Some(page_size);
+        self
+    }
+
+    /// Whether the file may or must be created. Defaults to `OpenMode::CreateIfMissing`.
+    pub fn mode(mut self, mode: OpenMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Average write rate cap in bytes per second, see `DiskPageManager::set_write_budget`.
+    pub fn write_budget(mut self, bytes_per_sec: u64) -> Self {
+        self.write_budget = Some(bytes_per_sec);
+        self
+    }
+
+    /// Keep up to `capacity` pages in an LRU page cache. No cache by default.
+    pub fn cache_capacity(mut self, capacity: usize) -> Self {
+        self.cache_capacity = Some(capacity);
+        self
+    }
+
+    /// Application id stamped into new databases and required of existing ones, which fail to
+    /// open with `SerializerError::AppIdMismatch` if theirs differs. Not checked by default.
+    pub fn app_id(mut self, app_id: [u8; 4]) -> Self {
+        self.app_id = Some(app_id);
+        self
+    }
+
+    /// Barriers used when the file grows, see `SyncMode`. Defaults to `SyncMode::Normal`.
+    pub fn sync_mode(mut self, sync_mode: SyncMode) -> Self {
+        self.sync_mode = sync_mode;
+        self
+    }
+
+    /// Rewrite a saved freelist chain into the fewest densely packed pages while opening, see
+    /// `DiskPageManager::compact_freelist`. Slows down `open` for faster allocation after.
+    /// Off by default.
+    pub fn compact_freelist_on_open(mut self, compact: bool) -> Self {
+        self.compact_freelist_on_open = compact;
+        self
+    }
+
+    /// Keep a copy of the header on page 1 of a new database, written and synced before page 0
+    /// is, so `open` can recover from a torn header write. Costs a page and two syncs per
+    /// header update. Only applies when the database is created; off by default.
+    pub fn header_backup(mut self, header_backup: bool) -> Self {
+        self.header_backup = header_backup;
+        self
+    }
+
+    /// Re-read every page written since the previous `sync` once it is synced, failing with
+    /// `PageManagerError::ChecksumMismatch` if the disk doesn't return what was written.
+    /// Trades throughput for catching a silently failing disk right away. Off by default.
+    pub fn verify_after_sync(mut self, verify: bool) -> Self {
+        self.verify_after_sync = verify;
+        self
+    }
+
+    /// Checkpoint the WAL in `close`, see `DiskPageManager::checkpoint`, so the next `open`
+    /// has nothing to replay. Batches already clear the WAL once they commit, so this only
+    /// matters after a commit failed between logging and applying. Off by default.
+    pub fn checkpoint_on_close(mut self, checkpoint: bool) -> Self {
+        self.checkpoint_on_close = checkpoint;
+        self
+    }
+
+    /// Open the database at `path` with the collected options.
+    pub fn open<P: AsRef<Path>>(self, path: P) -> Result<DiskPageManager, PageManagerError> {
+        let mut manager = DiskPageManager::open_inner(path, self.page_size, self.mode, self.app_id, self.header_backup)?;
+        manager.set_write_budget(self.write_budget);
+        manager.cache = self.cache_capacity.map(PageCache::new);
+        manager.sync_mode = self.sync_mode;
+        manager.unverified_writes = self.verify_after_sync.then(BTreeMap::new);
+        manager.checkpoint_on_close = self.checkpoint_on_close;
+        if self.compact_freelist_on_open && manager.header.freelist_head_page != 0 {
+            manager.compact_freelist()?;
+        }
+        Ok(manager)
+    }
+}
+
+/// Rate limiter for page writes, keeping the average write rate under a byte budget
+struct WriteThrottle {
+    bytes_per_sec: u64,
+    started: Instant,
+    bytes_written: u64,
+}
+
+impl WriteThrottle {
+    fn new(bytes_per_sec: u64) -> Self {
+        Self {
+            bytes_per_sec,
+            started: Instant::now(),
+            bytes_written: 0,
+        }
+    }
+
+    /// Block until writing `len` more bytes keeps the average rate within budget
+    fn admit(&mut self, len: u64) {
+        self.bytes_written += len;
+        let due = Duration::from_secs_f64(self.bytes_written as f64 / self.bytes_per_sec as f64);
+        let elapsed = self.started.elapsed();
+        if due > elapsed {
+            thread::sleep(due - elapsed);
+        }
+    }
+}
+
 /// Manages raw pages within the database file
 pub struct DiskPageManager {
     file: File,
     page_size: u64,
     header: DatabaseHeader,
-    // freelist: Vec<PageId>,
-    // TODO: add buffer pool or cache for performance
+    freelist: Vec<PageId>,
+    write_throttle: Option<WriteThrottle>,
+    observer: Option<Box<dyn PageObserver>>,
+    cache: Option<PageCache>,
+    wal_path: PathBuf,
+    sync_mode: SyncMode,
+    /// Checksums of the pages written since the last `sync`, kept when `verify_after_sync` is on
+    unverified_writes: Option<BTreeMap<PageId, u32>>,
+    checkpoint_on_close: bool,
+    #[cfg(test)]
+    faults: FaultInjection,
 }
 
 impl DiskPageManager {
+    /// Start building a page manager with non-default options.
+    pub fn builder() -> DiskPageManagerBuilder {
+        DiskPageManagerBuilder::default()
+    }
+
     /// Open or create a database file at `path`, setting the page size.
     pub fn open<P: AsRef<Path>>(path: P, page_size: u64) -> Result<Self, PageManagerError> {
-        if !path.as_ref().exists() {
-            let mut file = File::create(path.as_ref())?;
-            let header = DatabaseHeader::new(page_size);
-            file.write_all(&header.serialize())?;
-            Ok(Self {
+        Self::open_with_mode(path, page_size, OpenMode::CreateIfMissing)
+    }
+
+    /// Open or create a database file at `path` with an LRU cache of up to `cache_capacity`
+    /// pages. Writes stay in the cache until evicted or flushed by `sync`.
+    pub fn open_with_cache<P: AsRef<Path>>(path: P, page_size: u64, cache_capacity: usize) -> Result<Self, PageManagerError> {
+        let mut manager = Self::open(path, page_size)?;
+        manager.cache = Some(PageCache::new(cache_capacity));
+        Ok(manager)
+    }
+
+    /// Open an existing database file at `path`, using whatever page size its header stores.
+    pub fn open_any<P: AsRef<Path>>(path: P) -> Result<Self, PageManagerError> {
+        Self::open_inner(path, None, OpenMode::OpenExisting, None, false)
+    }
+
+    /// Open a database file at `path`, with `mode` deciding whether it may or must be created.
+    ///
+    /// For an existing file, `page_size` must match the one stored in its header, otherwise
+    /// `PageManagerError::PageSizeMismatch` is returned. Use `open_any` to trust the header.
+    pub fn open_with_mode<P: AsRef<Path>>(path: P, page_size: u64, mode: OpenMode) -> Result<Self, PageManagerError> {
+        Self::open_inner(path, Some(page_size), mode, None, false)
+    }
+
+    /// Shared implementation of the `open*` functions. A `None` page size accepts whatever an
+    /// existing file stores and uses `DEFAULT_PAGE_SIZE` for new ones. Likewise `app_id` is
+    /// only checked against existing files when given, and `header_backup` only affects new ones.
+    fn open_inner<P: AsRef<Path>>(
+        path: P,
+        page_size: Option<u64>,
+        mode: OpenMode,
+        app_id: Option<[u8; 4]>,
+        header_backup: bool,
+    ) -> Result<Self, PageManagerError> {
+        if let Some(page_size) = page_size {
+            validate_page_size(page_size).map_err(|_| PageManagerError::InvalidPageSize(page_size))?;
+        }
+
+        let exists = path.as_ref().exists();
+        match (mode, exists) {
+            (OpenMode::OpenExisting, false) => return Err(PageManagerError::NotFound),
+            (OpenMode::CreateNew, true) => return Err(PageManagerError::AlreadyExists),
+            _ => {}
+        }
+
+        let wal_path = wal::wal_path_for(path.as_ref());
+        if !exists {
+            let page_size = page_size.unwrap_or(DEFAULT_PAGE_SIZE);
+            // A WAL left over from a previous database at this path must not be replayed
+            // into the new one
+            if wal_path.exists() {
+                std::fs::remove_file(&wal_path)?;
+            }
+            let file = OpenOptions::new().read(true).write(true).create_new(true).open(path.as_ref())?;
+            let mut header = DatabaseHeader::new(page_size)?;
+            header.app_id = app_id.unwrap_or_default();
+            if header_backup {
+                header.backup_page = HEADER_BACKUP_PAGE as u64;
+                header.page_count += 1;
+            }
+            file.set_len(header.page_count * page_size)?;
+            let mut manager = Self {
                 file,
                 page_size,
                 header,
-            })
+                freelist: vec![],
+                write_throttle: None,
+                observer: None,
+                cache: None,
+                wal_path,
+                sync_mode: SyncMode::Normal,
+                unverified_writes: None,
+                checkpoint_on_close: false,
+                #[cfg(test)]
+                faults: FaultInjection::default(),
+            };
+            manager.write_header()?;
+            Ok(manager)
         } else {
-            let mut file = File::open(path.as_ref())?;
-            let mut buf = vec![0u8; page_size as usize];
+            let mut file = OpenOptions::new().read(true).write(true).open(path.as_ref())?;
+            let mut buf = vec![0u8; HEADER_SERIALIZED_LEN];
             file.read_exact(&mut buf)?;
-            let header = DatabaseHeader::deserialize(&buf)?;
-            Ok(Self {
+            let primary = DatabaseHeader::deserialize(&buf);
+            let backup = Self::read_backup_header(&file, primary.as_ref().ok())?;
+            // The backup slot is synced before page 0 is rewritten, so it wins over a torn
+            // primary and over one a crash left a write behind
+            let (header, recovered) = match (primary, backup) {
+                (Ok(primary), Some(backup)) if backup.sequence > primary.sequence => (backup, true),
+                (Ok(primary), _) => (primary, false),
+                (Err(_), Some(backup)) => (backup, true),
+                // The magic is checked before the checksum, so a checksum failure means the
+                // header is ours but was only partially rewritten
+                (Err(SerializerError::ChecksumMismatch { expected, found }), None) => {
+                    return Err(PageManagerError::TornHeaderWrite { expected, found });
+                }
+                (Err(e), None) => return Err(e.into()),
+            };
+            validate_page_size(header.page_size).map_err(|_| PageManagerError::InvalidPageSize(header.page_size))?;
+            if let Some(expected) = app_id {
+                header.check_app_id(expected)?;
+            }
+            if let Some(requested) = page_size.filter(|&requested| requested != header.page_size) {
+                return Err(PageManagerError::PageSizeMismatch {
+                    requested,
+                    stored: header.page_size,
+                });
+            }
+            let mut manager = Self {
                 file,
                 page_size: header.page_size,
                 header,
-            })
+                freelist: vec![],
+                write_throttle: None,
+                observer: None,
+                cache: None,
+                wal_path,
+                sync_mode: SyncMode::Normal,
+                unverified_writes: None,
+                checkpoint_on_close: false,
+                #[cfg(test)]
+                faults: FaultInjection::default(),
+            };
+            if recovered {
+                manager.write_header()?;
+            }
+            wal::replay(&mut manager)?;
+            manager.reconcile_file_length()?;
+            manager.load_freelist()?;
+            Ok(manager)
+        }
+    }
+
+    /// Read the header copy from the backup slot, if the database keeps one. Without a
+    /// readable primary header to say where the slot is, page 1 is tried at every supported
+    /// page size.
+    fn read_backup_header(file: &File, primary: Option<&DatabaseHeader>) -> Result<Option<DatabaseHeader>, PageManagerError> {
+        let candidates: Vec<(u64, u64)> = match primary {
+            Some(header) if header.backup_page == 0 => return Ok(None),
+            Some(header) => vec![(header.page_size, header.backup_page)],
+            None => std::iter::successors(Some(MIN_PAGE_SIZE), |&size| Some(size * 2))
+                .take_while(|&size| size <= MAX_PAGE_SIZE)
+                .map(|size| (size, HEADER_BACKUP_PAGE as u64))
+                .collect(),
+        };
+
+        let mut buf = vec![0u8; HEADER_SERIALIZED_LEN];
+        for (page_size, backup_page) in candidates {
+            match file.read_exact_at(&mut buf, backup_page * page_size) {
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => continue,
+                result => result?,
+            }
+            match DatabaseHeader::deserialize(&buf) {
+                Ok(header) if header.page_size == page_size && header.backup_page == backup_page => return Ok(Some(header)),
+                _ => {}
+            }
         }
+        Ok(None)
     }
 
-    /// Read the page `page_id` into `buf` (must be at least page_size bytes).
+    /// Read the page `page_id` into `buf` (exactly page_size bytes).
+    ///
+    /// Every page but the header ends with a CRC32 of the rest of the page, which is verified
+    /// here. A page that was allocated but never written is all zeros and is accepted as is.
     pub fn read_page(&mut self, page_id: PageId, buf: &mut [u8]) -> Result<(), PageManagerError> {
-        self.file.read_at(buf, page_id as u64 * self.page_size)?;
+        if buf.len() as u64 != self.page_size {
+            return Err(PageManagerError::BadPageLength {
+                expected: self.page_size,
+                got: buf.len() as u64,
+            });
+        }
+        let cached = match self.cache.as_mut() {
+            Some(cache) if page_id != 0 => cache.get(page_id).map(|data| buf.copy_from_slice(data)).is_some(),
+            _ => false,
+        };
+
+        if !cached {
+            retry_interrupted(|| self.file.read_at(buf, page_offset(page_id, self.page_size)))?;
+            if page_id != 0 {
+                verify_page_checksum(page_id, buf)?;
+                let evicted = self.cache.as_mut().and_then(|cache| cache.insert(page_id, buf.to_vec(), false));
+                if let Some((evicted_id, data)) = evicted {
+                    self.write_to_disk(evicted_id, &data)?;
+                }
+            }
+        }
+        if let Some(observer) = self.observer.as_mut() {
+            observer.on_read(page_id, buf);
+        }
         Ok(())
     }
 
+    /// Start a batch of page writes that is applied atomically on commit, going through the
+    /// write-ahead log next to the database file.
+    pub fn begin_batch(&mut self) -> WalBatch<'_> {
+        WalBatch::new(self)
+    }
+
+    /// Path of the write-ahead log used by `begin_batch`.
+    pub fn wal_path(&self) -> &Path {
+        &self.wal_path
+    }
+
     /// Write `buf` (exactly page_size bytes) to page `page_id`.
+    ///
+    /// Only the first `usable_page_size()` bytes are stored as given; the last 4 bytes are
+    /// replaced by the page checksum (except on the header page, which is written verbatim).
     pub fn write_page(&mut self, page_id: PageId, buf: &[u8]) -> Result<(), PageManagerError> {
-        self.file.write_at(buf, page_id as u64 * self.page_size)?;
+        if buf.len() as u64 != self.page_size {
+            return Err(PageManagerError::BadPageLength {
+                expected: self.page_size,
+                got: buf.len() as u64,
+            });
+        }
+        let mut page = buf.to_vec();
+        if page_id != 0 {
+            let usable = self.usable_page_size() as usize;
+            let checksum = crc32(&page[..usable]);
+            page[usable..].copy_from_slice(&checksum.to_le_bytes());
+        }
+
+        match self.cache.as_mut() {
+            Some(cache) if page_id != 0 => {
+                if let Some((evicted_id, data)) = cache.insert(page_id, page.clone(), true) {
+                    self.write_to_disk(evicted_id, &data)?;
+                }
+            }
+            _ => self.write_to_disk(page_id, &page)?,
+        }
+        if let Some(observer) = self.observer.as_mut() {
+            observer.on_write(page_id, &page);
+        }
+        Ok(())
+    }
+
+    /// Write a complete page (checksum included) straight to the file
+    fn write_to_disk(&mut self, page_id: PageId, page: &[u8]) -> Result<(), PageManagerError> {
+        if let Some(throttle) = self.write_throttle.as_mut() {
+            throttle.admit(page.len() as u64);
+        }
+        if let Some(unverified) = self.unverified_writes.as_mut() {
+            unverified.insert(page_id, crc32(&page[..page.len() - PAGE_CHECKSUM_LEN]));
+        }
+        #[cfg(test)]
+        if self.faults.drop_writes {
+            return Ok(());
+        }
+        retry_interrupted(|| self.file.write_at(page, page_offset(page_id, self.page_size)))?;
+        Ok(())
+    }
+
+    /// Hit/miss/eviction counters of the page cache, all zero if caching is off.
+    pub fn cache_stats(&self) -> CacheStats {
+        self.cache.as_ref().map(PageCache::stats).unwrap_or_default()
+    }
+
+    /// Write every dirty cached page back to the file
+    fn flush_cache(&mut self) -> Result<(), PageManagerError> {
+        let dirty = self.cache.as_mut().map(PageCache::take_dirty).unwrap_or_default();
+        for (page_id, data) in dirty {
+            self.write_to_disk(page_id, &data)?;
+        }
+        Ok(())
+    }
+
+    /// Bytes per page available to callers, i.e. `page_size` minus the trailing checksum.
+    pub fn usable_page_size(&self) -> u64 {
+        self.page_size - PAGE_CHECKSUM_LEN as u64
+    }
+
+    /// Cap page writes at `bytes_per_sec` on average, blocking `write_page` as needed so
+    /// background work doesn't saturate shared storage. `None` removes the limit.
+    pub fn set_write_budget(&mut self, bytes_per_sec: Option<u64>) {
+        self.write_throttle = bytes_per_sec.filter(|&rate| rate > 0).map(WriteThrottle::new);
+    }
+
+    /// Register `observer` to be called after every page read and write, replacing any
+    /// previous one. `None` removes it.
+    pub fn set_observer(&mut self, observer: Option<Box<dyn PageObserver>>) {
+        self.observer = observer;
+    }
+
+    /// Size of every page in this database, in bytes.
+    pub fn page_size(&self) -> u64 {
+        self.page_size
+    }
+
+    /// ID of the schema root page as recorded in the header. `0` means no root was stored yet.
+    pub fn schema_root_page(&self) -> PageId {
+        self.header.schema_root_page as PageId
+    }
+
+    /// Record `page_id` as the schema root page and rewrite the header on page 0.
+    pub fn set_schema_root_page(&mut self, page_id: PageId) -> Result<(), PageManagerError> {
+        self.header.schema_root_page = page_id as u64;
+        self.write_header()
+    }
+
+    /// Bring the file length and `page_count` back in line after an unclean shutdown.
+    ///
+    /// A crash can leave a partially written page at the end of the file, or full pages that
+    /// reached the disk before the header recording them did. The partial tail is truncated
+    /// away and every remaining full page is adopted. A file shorter than `page_count` says
+    /// is zero-extended so reads of recorded pages don't run past EOF.
+    fn reconcile_file_length(&mut self) -> Result<(), PageManagerError> {
+        let len = self.file.metadata()?.len();
+        let full_pages = len / self.page_size;
+
+        if full_pages > self.header.page_count {
+            self.header.page_count = full_pages;
+            self.write_header()?;
+        }
+
+        let expected_len = self.header.page_count * self.page_size;
+        if len != expected_len {
+            self.file.set_len(expected_len)?;
+        }
+        Ok(())
+    }
+
+    /// Serialize the in-memory header back to page 0, bumping its sequence number.
+    ///
+    /// With a backup slot the copy is written and synced first, then page 0 is written and
+    /// synced, so at least one of the two holds an intact header at any point.
+    fn write_header(&mut self) -> Result<(), PageManagerError> {
+        self.header.sequence += 1;
+        let bytes = self.header.serialize();
+        if self.header.backup_page != 0 {
+            // A full checksummed page, so `scrub` doesn't report the slot
+            let mut backup = vec![0u8; self.page_size as usize];
+            backup[..bytes.len()].copy_from_slice(&bytes);
+            let usable = self.usable_page_size() as usize;
+            let checksum = crc32(&backup[..usable]);
+            backup[usable..].copy_from_slice(&checksum.to_le_bytes());
+            let offset = page_offset(self.header.backup_page as PageId, self.page_size);
+            retry_interrupted(|| self.file.write_at(&backup, offset))?;
+            self.file.sync_data()?;
+        }
+        retry_interrupted(|| self.file.write_at(&bytes, 0))?;
+        if self.header.backup_page != 0 {
+            self.file.sync_data()?;
+        }
         Ok(())
     }
 
     /// Allocate a new page (reuse from freelist or extend file).
+    ///
+    /// A fresh page gets the id `page_count` had before the allocation, since page 0 is the
+    /// header. The file is grown first and the header rewritten after, so a crash in between
+    /// leaves an extra page that `open` adopts rather than a recorded page that is missing.
     pub fn alloc_page(&mut self) -> Result<PageId, PageManagerError> {
-        unimplemented!()
+        if let Some(page_id) = self.freelist.pop() {
+            self.invalidate_saved_freelist()?;
+            return Ok(page_id);
+        }
+        self.extend_file(1)
+    }
+
+    /// Grow the file by `n` pages, record them in the header and return the first new id.
+    ///
+    /// In `SyncMode::Full` the new page bodies are written and synced before the header is
+    /// rewritten, and the header is synced after; otherwise only the ordering is kept.
+    fn extend_file(&mut self, n: u64) -> Result<PageId, PageManagerError> {
+        let first = self.header.page_count;
+        let page_count = first + n;
+        match self.sync_mode {
+            SyncMode::Normal => self.file.set_len(page_count * self.page_size)?,
+            SyncMode::Full => {
+                let body = vec![0u8; self.page_size as usize];
+                for page_id in first..page_count {
+                    retry_interrupted(|| self.file.write_at(&body, page_offset(page_id as PageId, self.page_size)))?;
+                }
+                self.file.sync_data()?;
+            }
+        }
+        #[cfg(test)]
+        if self.faults.crash_before_header {
+            return Err(std::io::Error::other("injected crash before the header write").into());
+        }
+
+        self.header.page_count = page_count;
+        self.write_header()?;
+        if self.sync_mode == SyncMode::Full {
+            self.file.sync_data()?;
+        }
+        Ok(first as PageId)
+    }
+
+    /// Allocate `n` physically contiguous pages and return the id of the first one.
+    ///
+    /// A matching run of free pages is reused if the freelist has one, otherwise the file
+    /// is extended by `n` pages.
+    pub fn alloc_contiguous(&mut self, n: u32) -> Result<PageId, PageManagerError> {
+        if n == 0 {
+            return Err(PageManagerError::InvalidArgument("cannot allocate an empty run of pages".to_string()));
+        }
+
+        let mut free = self.freelist.clone();
+        free.sort_unstable();
+        let run_start = free
+            .windows(n as usize)
+            .find(|run| run[n as usize - 1] - run[0] == n - 1)
+            .map(|run| run[0]);
+
+        if let Some(first) = run_start {
+            self.freelist.retain(|&page_id| page_id < first || page_id >= first + n);
+            self.invalidate_saved_freelist()?;
+            return Ok(first);
+        }
+
+        self.extend_file(n as u64)
+    }
+
+    /// Copy `page_ids` from `src` into freshly allocated pages of this database and return the
+    /// `(source id, destination id)` mapping, in input order. Both databases must use the
+    /// same page size.
+    pub fn copy_pages_from(&mut self, src: &mut DiskPageManager, page_ids: &[PageId]) -> Result<Vec<(PageId, PageId)>, PageManagerError> {
+        if src.page_size != self.page_size {
+            return Err(PageManagerError::PageSizeMismatch {
+                requested: src.page_size,
+                stored: self.page_size,
+            });
+        }
+
+        let mut buf = vec![0u8; self.page_size as usize];
+        let mut mapping = Vec::with_capacity(page_ids.len());
+        for &page_id in page_ids {
+            src.read_page(page_id, &mut buf)?;
+            let new_id = self.alloc_page()?;
+            self.write_page(new_id, &buf)?;
+            mapping.push((page_id, new_id));
+        }
+        Ok(mapping)
+    }
+
+    /// Free pages with ids in `[lo, hi)`, in ascending order. Useful for planning contiguous
+    /// allocations or defragmentation.
+    pub fn free_pages_in_range(&self, lo: PageId, hi: PageId) -> Result<Vec<PageId>, PageManagerError> {
+        let mut free: Vec<PageId> = self.freelist
+            .iter()
+            .copied()
+            .filter(|&page_id| lo <= page_id && page_id < hi)
+            .collect();
+        free.sort_unstable();
+        Ok(free)
+    }
+
+    /// Verify the checksum of every page in `[from, to)` as stored on disk and return the ids
+    /// of the pages that fail, e.g. from a periodic bit-rot check. The range is clipped to the
+    /// pages that exist and never includes the header page, which has its own checksum.
+    ///
+    /// Pages are read one at a time into a single buffer, bypassing the page cache, and
+    /// nothing is written, so writes still waiting in the cache aren't covered.
+    pub fn scrub(&mut self, from: PageId, to: PageId) -> Result<Vec<PageId>, PageManagerError> {
+        let to = (to as u64).min(self.header.page_count) as PageId;
+        let mut buf = vec![0u8; self.page_size as usize];
+        let mut failed = vec![];
+        for page_id in from.max(1)..to {
+            retry_interrupted(|| self.file.read_at(&mut buf, page_offset(page_id, self.page_size)))?;
+            if verify_page_checksum(page_id, &buf).is_err() {
+                failed.push(page_id);
+            }
+        }
+        Ok(failed)
+    }
+
+    /// Report the free page ratio and the largest contiguous free run, to help decide when
+    /// the database is worth rebuilding.
+    pub fn fragmentation(&self) -> FragReport {
+        let mut free = self.freelist.clone();
+        free.sort_unstable();
+
+        let mut largest_free_run = 0;
+        let mut run = 0;
+        for (i, &page_id) in free.iter().enumerate() {
+            run = if i > 0 && free[i - 1] + 1 == page_id { run + 1 } else { 1 };
+            largest_free_run = largest_free_run.max(run);
+        }
+
+        let total_pages = self.header.page_count;
+        let free_pages = free.len() as u64;
+        FragReport {
+            total_pages,
+            free_pages,
+            free_ratio: free_pages as f64 / total_pages as f64,
+            largest_free_run,
+        }
     }
 
     /// Free the given page, adding it to the freelist.
+    /// The freelist is LIFO, so the most recently freed page is the next one handed out.
     pub fn free_page(&mut self, page_id: PageId) -> Result<(), PageManagerError> {
-        unimplemented!()
+        if page_id == 0 || page_id as u64 >= self.header.page_count {
+            return Err(PageManagerError::InvalidPage(page_id));
+        }
+        if self.freelist.contains(&page_id) {
+            return Err(PageManagerError::DoubleFree(page_id));
+        }
+        self.freelist.push(page_id);
+        Ok(())
     }
 
     /// Persist the freelist back to disk if using on-disk freelist pages.
+    ///
+    /// The chain is stored in pages taken from the freelist itself: they are free anyway, and
+    /// every id (including their own) is listed in the chain, so loading it back makes them
+    /// allocatable again. The chain pages are synced before the header points at them, and
+    /// the header is synced after, so a crash never leaves a header naming unwritten pages.
     pub fn save_freelist(&mut self) -> Result<(), PageManagerError> {
-        unimplemented!()
+        let capacity = FreeListPage::capacity(self.usable_page_size());
+        let chunks: Vec<Vec<PageId>> = self.freelist.chunks(capacity).map(<[PageId]>::to_vec).collect();
+        let chain: Vec<PageId> = self.freelist[..chunks.len()].to_vec();
+
+        for (i, chunk) in chunks.into_iter().enumerate() {
+            let page = FreeListPage {
+                page_size: self.usable_page_size(),
+                next_page: chain.get(i + 1).map_or(0, |&next| next as u64),
+                freelist: chunk,
+            };
+            let mut bytes = page.serialize();
+            bytes.resize(self.page_size as usize, 0);
+            self.write_page(chain[i], &bytes)?;
+        }
+        self.sync()?;
+
+        self.header.freelist_head_page = chain.first().map_or(0, |&head| head as u64);
+        self.write_header()?;
+        self.sync()
+    }
+
+    /// Rewrite the freelist chain into the fewest pages it fits in, sorted so the lowest free
+    /// ids are handed out first.
+    pub fn compact_freelist(&mut self) -> Result<(), PageManagerError> {
+        self.freelist.sort_unstable_by(|a, b| b.cmp(a));
+        self.save_freelist()
+    }
+
+    /// Rebuild the in-memory freelist from the on-disk chain starting at `freelist_head_page`.
+    ///
+    /// Every listed id must be a data page inside the file and appear only once, otherwise the
+    /// same page could be handed out twice.
+    fn load_freelist(&mut self) -> Result<(), PageManagerError> {
+        let mut seen = std::collections::HashSet::new();
+        let mut next_page = self.header.freelist_head_page;
+        let mut buf = vec![0u8; self.page_size as usize];
+        let mut visited = 0;
+        while next_page != 0 {
+            if next_page >= self.header.page_count || visited >= self.header.page_count {
+                return Err(SerializerError::InsufficientData(format!("freelist chain is broken at page {}", next_page)).into());
+            }
+            self.read_page(next_page as PageId, &mut buf)?;
+            let usable = self.usable_page_size();
+            let page = FreeListPage::deserialize(&buf[..usable as usize], usable)?;
+            for &page_id in &page.freelist {
+                if page_id == 0 || page_id as u64 >= self.header.page_count {
+                    return Err(SerializerError::InsufficientData(format!("freelist lists invalid page {}", page_id)).into());
+                }
+                if !seen.insert(page_id) {
+                    return Err(SerializerError::InsufficientData(format!("freelist lists page {} twice", page_id)).into());
+                }
+            }
+            self.freelist.extend(page.freelist);
+            next_page = page.next_page;
+            visited += 1;
+        }
+        Ok(())
+    }
+
+    /// Forget the on-disk freelist chain once pages are handed out from the freelist: one of
+    /// the chain pages may be reused and overwritten. Leaking free pages after a crash is
+    /// better than reading garbage as a freelist. `save_freelist` writes a fresh chain.
+    fn invalidate_saved_freelist(&mut self) -> Result<(), PageManagerError> {
+        if self.header.freelist_head_page != 0 {
+            self.header.freelist_head_page = 0;
+            self.write_header()?;
+        }
+        Ok(())
     }
 
-    /// Flush all pending writes to disk.
+    /// Flush all pending writes to disk, including dirty cached pages.
+    ///
+    /// With `verify_after_sync` on, the pages written since the previous sync are then read
+    /// back and compared against what was written.
     pub fn sync(&mut self) -> Result<(), PageManagerError> {
+        self.flush_cache()?;
         self.file.sync_data()?;
+        let Some(unverified) = self.unverified_writes.as_mut().map(std::mem::take) else {
+            return Ok(());
+        };
+        let mut buf = vec![0u8; self.page_size as usize];
+        let usable = self.usable_page_size() as usize;
+        for (page_id, expected) in unverified {
+            retry_interrupted(|| self.file.read_at(&mut buf, page_offset(page_id, self.page_size)))?;
+            let found = crc32(&buf[..usable]);
+            if found != expected {
+                return Err(PageManagerError::ChecksumMismatch { page_id, expected, found });
+            }
+        }
         Ok(())
     }
 
-    /// Close the page manager and its underlying file.
+    /// Apply a committed batch still sitting in the WAL to the database and truncate the WAL,
+    /// the same way `open` replays one. Returns whether anything was applied. The header keeps
+    /// no checkpoint position: an empty WAL is what marks everything as checkpointed.
+    pub fn checkpoint(&mut self) -> Result<bool, PageManagerError> {
+        wal::replay(self)
+    }
+
+    /// Close the page manager and its underlying file, persisting the freelist first.
+    /// With `checkpoint_on_close` on, the WAL is checkpointed before that.
     pub fn close(&mut self) -> Result<(), PageManagerError> {
+        if self.checkpoint_on_close {
+            self.checkpoint()?;
+        }
+        self.save_freelist()?;
         self.sync()?;
         Ok(())
     }
 }
 
+impl Pager for DiskPageManager {
+    fn page_size(&self) -> u64 {
+        DiskPageManager::page_size(self)
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::fs;
-    use tempfile::tempdir;
+    fn usable_page_size(&self) -> u64 {
+        DiskPageManager::usable_page_size(self)
+    }
 
-    #[test]
-    fn test_open_new_file() {
-        let dir = tempdir().unwrap();
-        let db_path = dir.path().join("test_open.db");
-        
-        let page_size = 4096;
-        let manager = DiskPageManager::open(&db_path, page_size).unwrap();
-        
-        assert_eq!(manager.page_size, page_size);
-        assert!(db_path.exists());
-        
-        // Clean up
-        drop(manager);
-        fs::remove_file(db_path).unwrap();
+    fn read_page(&mut self, page_id: PageId, buf: &mut [u8]) -> Result<(), PageManagerError> {
+        DiskPageManager::read_page(self, page_id, buf)
     }
-    
-    #[test]
-    fn test_write_and_read_page() {
-        let dir = tempdir().unwrap();
-        let db_path = dir.path().join("test_write_read.db");
-        
-        let page_size = 4096;
-        let mut manager = DiskPageManager::open(&db_path, page_size).unwrap();
-        
-        // Allocate a new page
-        let page_id = manager.alloc_page().unwrap();
-        
-        // Create test data
-        let mut write_buf = vec![0u8; page_size as usize];
-        for i in 0..page_size {
-            write_buf[i as usize] = (i % 256) as u8;
+
+    fn write_page(&mut self, page_id: PageId, buf: &[u8]) -> Result<(), PageManagerError> {
+        DiskPageManager::write_page(self, page_id, buf)
+    }
+
+    fn alloc_page(&mut self) -> Result<PageId, PageManagerError> {
+        DiskPageManager::alloc_page(self)
+    }
+
+    fn free_page(&mut self, page_id: PageId) -> Result<(), PageManagerError> {
+        DiskPageManager::free_page(self, page_id)
+    }
+
+    fn sync(&mut self) -> Result<(), PageManagerError> {
+        DiskPageManager::sync(self)
+    }
+
+    fn schema_root_page(&self) -> PageId {
+        DiskPageManager::schema_root_page(self)
+    }
+
+    fn set_schema_root_page(&mut self, page_id: PageId) -> Result<(), PageManagerError> {
+        DiskPageManager::set_schema_root_page(self, page_id)
+    }
+
+    fn cache_stats(&self) -> CacheStats {
+        DiskPageManager::cache_stats(self)
+    }
+
+    fn commit_pages(&mut self, pages: &[(PageId, Vec<u8>)], schema_root: PageId) -> Result<(), PageManagerError> {
+        let mut batch = self.begin_batch();
+        for (page_id, buf) in pages {
+            batch.write_page(*page_id, buf)?;
         }
-        
-        // Write the page
-        manager.write_page(page_id, &write_buf).unwrap();
-        
-        // Read the page back
-        let mut read_buf = vec![0u8; page_size as usize];
-        manager.read_page(page_id, &mut read_buf).unwrap();
-        
-        // Verify the data
-        assert_eq!(write_buf, read_buf);
-        
-        // Clean up
-        drop(manager);
-        fs::remove_file(db_path).unwrap();
+        batch.set_schema_root_page(schema_root)?;
+        batch.commit()
     }
-    
-    #[test]
-    fn test_alloc_and_free_page() {
-        let dir = tempdir().unwrap();
-        let db_path = dir.path().join("test_alloc_free.db");
-        
+}
+
+impl Drop for DiskPageManager {
+    fn drop(&mut self) {
+        // Best effort: don't lose cached writes when the manager goes away without a sync
+        let _ = self.flush_cache();
+    }
+}
+
+/// Page manager keeping every page in memory, for tests and ephemeral databases.
+///
+/// Mirrors `DiskPageManager`'s page API: page 0 is reserved for the header, new pages are
+/// zero-filled, and the last `PAGE_CHECKSUM_LEN` bytes of each page are reserved (though not
+/// checked) so that the same nodes fit in pages of either manager.
+pub struct MemoryPageManager {
+    page_size: u64,
+    pages: Vec<Vec<u8>>,
+    freelist: Vec<PageId>,
+    schema_root_page: PageId,
+}
+
+impl MemoryPageManager {
+    /// Create an empty database holding only the header page
+    pub fn new(page_size: u64) -> Self {
+        Self {
+            page_size,
+            pages: vec![vec![0u8; page_size as usize]],
+            freelist: vec![],
+            schema_root_page: 0,
+        }
+    }
+
+    /// Size of each page in bytes.
+    pub fn page_size(&self) -> u64 {
+        self.page_size
+    }
+
+    /// Bytes of each page available to callers, see `DiskPageManager::usable_page_size`.
+    pub fn usable_page_size(&self) -> u64 {
+        self.page_size - PAGE_CHECKSUM_LEN as u64
+    }
+
+    /// Number of pages, including the header page and free ones.
+    pub fn page_count(&self) -> u64 {
+        self.pages.len() as u64
+    }
+
+    /// Copy page `page_id` into `buf` (exactly page_size bytes).
+    pub fn read_page(&self, page_id: PageId, buf: &mut [u8]) -> Result<(), PageManagerError> {
+        self.check_len(buf.len())?;
+        let page = self.pages.get(page_id as usize).ok_or(PageManagerError::InvalidPage(page_id))?;
+        buf.copy_from_slice(page);
+        Ok(())
+    }
+
+    /// Replace page `page_id` with `buf` (exactly page_size bytes).
+    pub fn write_page(&mut self, page_id: PageId, buf: &[u8]) -> Result<(), PageManagerError> {
+        self.check_len(buf.len())?;
+        let page = self.pages.get_mut(page_id as usize).ok_or(PageManagerError::InvalidPage(page_id))?;
+        page.copy_from_slice(buf);
+        Ok(())
+    }
+
+    /// Allocate a page, reusing a freed one if there is any. Fresh pages are zero-filled.
+    pub fn alloc_page(&mut self) -> Result<PageId, PageManagerError> {
+        if let Some(page_id) = self.freelist.pop() {
+            return Ok(page_id);
+        }
+        self.pages.push(vec![0u8; self.page_size as usize]);
+        Ok((self.pages.len() - 1) as PageId)
+    }
+
+    /// Mark a page as free, with the same checks as `DiskPageManager::free_page`.
+    pub fn free_page(&mut self, page_id: PageId) -> Result<(), PageManagerError> {
+        if page_id == 0 || page_id as usize >= self.pages.len() {
+            return Err(PageManagerError::InvalidPage(page_id));
+        }
+        if self.freelist.contains(&page_id) {
+            return Err(PageManagerError::DoubleFree(page_id));
+        }
+        self.freelist.push(page_id);
+        Ok(())
+    }
+
+    /// Root page of the schema B-tree, 0 if none has been created yet.
+    pub fn schema_root_page(&self) -> PageId {
+        self.schema_root_page
+    }
+
+    /// Record the root page of the schema B-tree.
+    pub fn set_schema_root_page(&mut self, page_id: PageId) -> Result<(), PageManagerError> {
+        self.schema_root_page = page_id;
+        Ok(())
+    }
+
+    /// Nothing to flush for pages that only live in memory.
+    pub fn sync(&mut self) -> Result<(), PageManagerError> {
+        Ok(())
+    }
+
+    /// Reject buffers that aren't exactly one page long
+    fn check_len(&self, len: usize) -> Result<(), PageManagerError> {
+        if len as u64 != self.page_size {
+            return Err(PageManagerError::BadPageLength {
+                expected: self.page_size,
+                got: len as u64,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl Pager for MemoryPageManager {
+    fn page_size(&self) -> u64 {
+        MemoryPageManager::page_size(self)
+    }
+
+    fn usable_page_size(&self) -> u64 {
+        MemoryPageManager::usable_page_size(self)
+    }
+
+    fn read_page(&mut self, page_id: PageId, buf: &mut [u8]) -> Result<(), PageManagerError> {
+        MemoryPageManager::read_page(self, page_id, buf)
+    }
+
+    fn write_page(&mut self, page_id: PageId, buf: &[u8]) -> Result<(), PageManagerError> {
+        MemoryPageManager::write_page(self, page_id, buf)
+    }
+
+    fn alloc_page(&mut self) -> Result<PageId, PageManagerError> {
+        MemoryPageManager::alloc_page(self)
+    }
+
+    fn free_page(&mut self, page_id: PageId) -> Result<(), PageManagerError> {
+        MemoryPageManager::free_page(self, page_id)
+    }
+
+    fn sync(&mut self) -> Result<(), PageManagerError> {
+        MemoryPageManager::sync(self)
+    }
+
+    fn schema_root_page(&self) -> PageId {
+        MemoryPageManager::schema_root_page(self)
+    }
+
+    fn set_schema_root_page(&mut self, page_id: PageId) -> Result<(), PageManagerError> {
+        MemoryPageManager::set_schema_root_page(self, page_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::io::Write;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_open_new_file() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test_open.db");
+        
+        let page_size = 4096;
+        let manager = DiskPageManager::open(&db_path, page_size).unwrap();
+        
+        assert_eq!(manager.page_size, page_size);
+        assert!(db_path.exists());
+        
+        // Clean up
+        drop(manager);
+        fs::remove_file(db_path).unwrap();
+    }
+    
+    #[test]
+    fn test_write_and_read_page() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test_write_read.db");
+        
+        let page_size = 4096;
+        let mut manager = DiskPageManager::open(&db_path, page_size).unwrap();
+        
+        // Allocate a new page
+        let page_id = manager.alloc_page().unwrap();
+        
+        // Create test data
+        let mut write_buf = vec![0u8; page_size as usize];
+        for i in 0..page_size {
+            write_buf[i as usize] = (i % 256) as u8;
+        }
+        
+        // Write the page
+        manager.write_page(page_id, &write_buf).unwrap();
+        
+        // Read the page back
+        let mut read_buf = vec![0u8; page_size as usize];
+        manager.read_page(page_id, &mut read_buf).unwrap();
+        
+        // Verify the data, apart from the trailing checksum
+        let usable = manager.usable_page_size() as usize;
+        assert_eq!(write_buf[..usable], read_buf[..usable]);
+        
+        // Clean up
+        drop(manager);
+        fs::remove_file(db_path).unwrap();
+    }
+    
+    #[test]
+    fn test_alloc_and_free_page() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test_alloc_free.db");
+        
         let page_size = 4096;
         let mut manager = DiskPageManager::open(&db_path, page_size).unwrap();
         
@@ -191,12 +1289,46 @@ mod tests {
         
         // The new page should have the same ID as the freed page
         assert_eq!(page_id2, page_id4);
+        assert_ne!(page_id4, page_id1);
+        assert_ne!(page_id4, page_id3);
         
         // Clean up
         drop(manager);
         fs::remove_file(db_path).unwrap();
     }
     
+    #[test]
+    fn test_pages_are_page_indexed() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test_page_indexed.db");
+
+        let page_size = 4096;
+        let mut manager = DiskPageManager::open(&db_path, page_size).unwrap();
+        let page_ids: Vec<PageId> = (0..3).map(|_| manager.alloc_page().unwrap()).collect();
+        assert_eq!(page_ids, vec![1, 2, 3]);
+
+        for &page_id in &page_ids {
+            manager.write_page(page_id, &vec![page_id as u8; page_size as usize]).unwrap();
+        }
+        manager.sync().unwrap();
+
+        let usable = manager.usable_page_size() as usize;
+        let mut buf = vec![0u8; page_size as usize];
+        for &page_id in &page_ids {
+            manager.read_page(page_id, &mut buf).unwrap();
+            assert!(buf[..usable].iter().all(|&b| b == page_id as u8));
+        }
+
+        // Page n lives at byte n * page_size of the file
+        let raw = fs::read(&db_path).unwrap();
+        assert_eq!(raw.len() as u64, 4 * page_size);
+        for &page_id in &page_ids {
+            let start = page_offset(page_id, page_size) as usize;
+            assert_eq!(start, page_id as usize * page_size as usize);
+            assert!(raw[start..start + usable].iter().all(|&b| b == page_id as u8));
+        }
+    }
+
     #[test]
     fn test_sync_and_persistence() {
         let dir = tempdir().unwrap();
@@ -223,8 +1355,9 @@ mod tests {
         let mut read_buf = vec![0u8; page_size as usize];
         manager.read_page(page_id, &mut read_buf).unwrap();
         
-        // Verify the data persisted
-        assert_eq!(write_buf, read_buf);
+        // Verify the data persisted, apart from the trailing checksum
+        let usable = manager.usable_page_size() as usize;
+        assert_eq!(write_buf[..usable], read_buf[..usable]);
         
         // Clean up
         drop(manager);
@@ -265,5 +1398,760 @@ mod tests {
         drop(manager);
         fs::remove_file(db_path).unwrap();
     }
-}
 
+    #[test]
+    fn test_load_freelist_rejects_bad_ids() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test_bad_freelist.db");
+        let page_size = 4096;
+
+        for bad in [vec![0], vec![99], vec![2, 2]] {
+            let mut manager = DiskPageManager::open_with_mode(&db_path, page_size, OpenMode::CreateNew).unwrap();
+            let page_ids: Vec<PageId> = (0..3).map(|_| manager.alloc_page().unwrap()).collect();
+            manager.free_page(page_ids[0]).unwrap();
+            manager.close().unwrap();
+
+            // Rewrite the chain page with an id that must never be handed out
+            let head = manager.header.freelist_head_page as PageId;
+            let page = FreeListPage { page_size: manager.usable_page_size(), next_page: 0, freelist: bad };
+            let mut bytes = page.serialize();
+            bytes.resize(page_size as usize, 0);
+            manager.write_page(head, &bytes).unwrap();
+            manager.sync().unwrap();
+            drop(manager);
+
+            assert!(matches!(DiskPageManager::open(&db_path, page_size), Err(PageManagerError::BadPageFormat(_))));
+            fs::remove_file(&db_path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_crash_between_alloc_body_and_header_reopens_consistently() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test_alloc_crash.db");
+        let page_size = 4096;
+
+        let mut manager = DiskPageManager::builder().page_size(page_size).sync_mode(SyncMode::Full).open(&db_path).unwrap();
+        let first = manager.alloc_page().unwrap();
+        manager.faults.crash_before_header = true;
+        assert!(manager.alloc_page().is_err());
+        assert_eq!(manager.header.page_count, first as u64 + 1);
+        drop(manager);
+
+        // The synced page body is adopted; the header never counts a page the file lacks
+        let mut manager = DiskPageManager::open(&db_path, page_size).unwrap();
+        assert_eq!(manager.header.page_count, first as u64 + 2);
+        assert_eq!(fs::metadata(&db_path).unwrap().len(), manager.header.page_count * page_size);
+        assert_eq!(manager.alloc_page().unwrap(), first + 2);
+    }
+
+    #[test]
+    fn test_compact_freelist_on_open() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test_compact_freelist.db");
+        let page_size = 4096;
+
+        // Hand-build a chain that spends a page per free id
+        let mut manager = DiskPageManager::open(&db_path, page_size).unwrap();
+        let page_ids: Vec<PageId> = (0..6).map(|_| manager.alloc_page().unwrap()).collect();
+        let free = [page_ids[1], page_ids[3], page_ids[4]];
+        for (i, &page_id) in free.iter().enumerate() {
+            let page = FreeListPage {
+                page_size: manager.usable_page_size(),
+                next_page: free.get(i + 1).map_or(0, |&next| next as u64),
+                freelist: vec![page_id],
+            };
+            let mut bytes = page.serialize();
+            bytes.resize(page_size as usize, 0);
+            manager.write_page(page_id, &bytes).unwrap();
+        }
+        manager.sync().unwrap();
+        manager.header.freelist_head_page = free[0] as u64;
+        manager.write_header().unwrap();
+        manager.sync().unwrap();
+        drop(manager);
+
+        let mut manager = DiskPageManager::builder().page_size(page_size).compact_freelist_on_open(true).open(&db_path).unwrap();
+        let mut buf = vec![0u8; page_size as usize];
+        manager.read_page(manager.header.freelist_head_page as PageId, &mut buf).unwrap();
+        let usable = manager.usable_page_size();
+        let head = FreeListPage::deserialize(&buf[..usable as usize], usable).unwrap();
+        assert_eq!(head.next_page, 0);
+        assert_eq!(head.freelist.len(), free.len());
+        drop(manager);
+
+        // The compacted chain lists the same free pages
+        let mut manager = DiskPageManager::open(&db_path, page_size).unwrap();
+        let mut reused: Vec<PageId> = (0..3).map(|_| manager.alloc_page().unwrap()).collect();
+        reused.sort_unstable();
+        assert_eq!(reused, free);
+        assert_eq!(manager.alloc_page().unwrap(), page_ids[5] + 1);
+    }
+
+    #[test]
+    fn test_verify_after_sync_catches_lost_writes() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test_verify_after_sync.db");
+        let page_size = 4096;
+
+        let mut manager = DiskPageManager::builder().page_size(page_size).verify_after_sync(true).open(&db_path).unwrap();
+        let page_id = manager.alloc_page().unwrap();
+        manager.write_page(page_id, &vec![1u8; page_size as usize]).unwrap();
+        manager.sync().unwrap();
+
+        manager.faults.drop_writes = true;
+        manager.write_page(page_id, &vec![2u8; page_size as usize]).unwrap();
+        let result = manager.sync();
+        assert!(matches!(result, Err(PageManagerError::ChecksumMismatch { page_id: id, .. }) if id == page_id));
+
+        // Without the flag the lost write goes unnoticed
+        let mut manager = DiskPageManager::open(&db_path, page_size).unwrap();
+        manager.faults.drop_writes = true;
+        manager.write_page(page_id, &vec![3u8; page_size as usize]).unwrap();
+        assert!(manager.sync().is_ok());
+    }
+
+    #[test]
+    fn test_alloc_contiguous_extends_file() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test_alloc_contiguous.db");
+
+        let page_size = 4096;
+        let mut manager = DiskPageManager::open(&db_path, page_size).unwrap();
+
+        let first = manager.alloc_contiguous(8).unwrap();
+        assert_eq!(first, 1);
+        assert_eq!(manager.header.page_count, 9);
+        assert_eq!(fs::metadata(&db_path).unwrap().len(), 9 * page_size);
+
+        // The run is really 8 sequential pages: the next one starts right after it
+        let next = manager.alloc_contiguous(1).unwrap();
+        assert_eq!(next, first + 8);
+    }
+
+    #[test]
+    fn test_alloc_contiguous_rejects_empty_run() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test_alloc_contiguous_empty.db");
+
+        let mut manager = DiskPageManager::open(&db_path, 4096).unwrap();
+        assert!(matches!(manager.alloc_contiguous(0), Err(PageManagerError::InvalidArgument(_))));
+        assert_eq!(manager.header.page_count, 1);
+    }
+
+    #[test]
+    fn test_alloc_contiguous_reuses_free_run() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test_alloc_contiguous_reuse.db");
+
+        let page_size = 4096;
+        let mut manager = DiskPageManager::open(&db_path, page_size).unwrap();
+        manager.alloc_contiguous(10).unwrap();
+
+        // Scattered single pages plus one run of three
+        manager.freelist = vec![2, 9, 4, 5, 6];
+
+        let first = manager.alloc_contiguous(3).unwrap();
+        assert_eq!(first, 4);
+        assert_eq!(manager.freelist, vec![2, 9]);
+        assert_eq!(manager.header.page_count, 11);
+    }
+
+    #[test]
+    fn test_reopen_after_partial_trailing_write() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test_unclean_shutdown.db");
+
+        let page_size = 4096;
+        let write_buf = vec![7u8; page_size as usize];
+        {
+            let mut manager = DiskPageManager::open(&db_path, page_size).unwrap();
+            // Page 1 reaches the disk but the header never records it
+            manager.write_page(1, &write_buf).unwrap();
+        }
+
+        // Simulate a torn write of the next page
+        let mut file = OpenOptions::new().append(true).open(&db_path).unwrap();
+        file.write_all(&[1u8; 100]).unwrap();
+        drop(file);
+
+        let mut manager = DiskPageManager::open(&db_path, page_size).unwrap();
+        assert_eq!(manager.header.page_count, 2);
+        assert_eq!(fs::metadata(&db_path).unwrap().len(), 2 * page_size);
+
+        let mut read_buf = vec![0u8; page_size as usize];
+        manager.read_page(1, &mut read_buf).unwrap();
+        let usable = manager.usable_page_size() as usize;
+        assert_eq!(read_buf[..usable], write_buf[..usable]);
+    }
+
+    #[test]
+    fn test_write_page_rejects_bad_length() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test_bad_length.db");
+
+        let page_size = 4096;
+        let mut manager = DiskPageManager::open(&db_path, page_size).unwrap();
+
+        let result = manager.write_page(1, &[0u8; 100]);
+        assert!(matches!(result, Err(PageManagerError::BadPageLength { expected: 4096, got: 100 })));
+
+        let result = manager.write_page(1, &[0u8; 4097]);
+        assert!(matches!(result, Err(PageManagerError::BadPageLength { expected: 4096, got: 4097 })));
+
+        // Nothing was written past the header page
+        assert_eq!(fs::metadata(&db_path).unwrap().len(), page_size);
+    }
+
+    #[test]
+    fn test_retry_interrupted() {
+        let mut calls = 0;
+        let result = retry_interrupted(|| {
+            calls += 1;
+            if calls == 1 {
+                Err(std::io::Error::from(std::io::ErrorKind::Interrupted))
+            } else {
+                Ok(4096)
+            }
+        });
+        assert_eq!(result.unwrap(), 4096);
+        assert_eq!(calls, 2);
+
+        // Other errors are propagated without retrying
+        let mut calls = 0;
+        let result: std::io::Result<usize> = retry_interrupted(|| {
+            calls += 1;
+            Err(std::io::Error::from(std::io::ErrorKind::PermissionDenied))
+        });
+        assert_eq!(result.unwrap_err().kind(), std::io::ErrorKind::PermissionDenied);
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn test_page_offset() {
+        assert_eq!(page_offset(0, 4096), 0);
+        assert_eq!(page_offset(3, 4096), 12288);
+        assert_eq!(page_offset(PageId::MAX, 65536), PageId::MAX as u64 * 65536);
+    }
+
+    #[test]
+    fn test_write_budget_throttles_writes() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test_write_budget.db");
+
+        let page_size = 4096;
+        let mut manager = DiskPageManager::open(&db_path, page_size).unwrap();
+        // Ten pages per second
+        manager.set_write_budget(Some(10 * page_size));
+
+        let started = Instant::now();
+        for page_id in 1..=5 {
+            manager.write_page(page_id, &vec![page_id as u8; page_size as usize]).unwrap();
+        }
+        assert!(started.elapsed() >= Duration::from_millis(450));
+
+        for page_id in 1..=5 {
+            let mut read_buf = vec![0u8; page_size as usize];
+            manager.read_page(page_id, &mut read_buf).unwrap();
+            assert!(read_buf[..manager.usable_page_size() as usize].iter().all(|&b| b == page_id as u8));
+        }
+    }
+
+    #[test]
+    fn test_open_modes_on_missing_file() {
+        let dir = tempdir().unwrap();
+        let page_size = 4096;
+
+        let path = dir.path().join("missing_existing.db");
+        let result = DiskPageManager::open_with_mode(&path, page_size, OpenMode::OpenExisting);
+        assert!(matches!(result, Err(PageManagerError::NotFound)));
+        assert!(!path.exists());
+
+        let path = dir.path().join("missing_create_new.db");
+        DiskPageManager::open_with_mode(&path, page_size, OpenMode::CreateNew).unwrap();
+        assert!(path.exists());
+
+        let path = dir.path().join("missing_create_if_missing.db");
+        DiskPageManager::open_with_mode(&path, page_size, OpenMode::CreateIfMissing).unwrap();
+        assert!(path.exists());
+    }
+
+    #[test]
+    fn test_open_modes_on_existing_file() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("existing.db");
+        let page_size = 4096;
+        drop(DiskPageManager::open(&path, page_size).unwrap());
+
+        DiskPageManager::open_with_mode(&path, page_size, OpenMode::OpenExisting).unwrap();
+        DiskPageManager::open_with_mode(&path, page_size, OpenMode::CreateIfMissing).unwrap();
+
+        let result = DiskPageManager::open_with_mode(&path, page_size, OpenMode::CreateNew);
+        assert!(matches!(result, Err(PageManagerError::AlreadyExists)));
+    }
+
+    #[test]
+    fn test_copy_pages_from() {
+        let dir = tempdir().unwrap();
+        let page_size = 4096;
+        let mut src = DiskPageManager::open(dir.path().join("src.db"), page_size).unwrap();
+        let mut dest = DiskPageManager::open(dir.path().join("dest.db"), page_size).unwrap();
+
+        let src_ids: Vec<PageId> = (0..4).map(|_| src.alloc_page().unwrap()).collect();
+        for &page_id in &src_ids {
+            src.write_page(page_id, &vec![page_id as u8 * 10; page_size as usize]).unwrap();
+        }
+        // Occupy a page in the destination so the ids can't line up by accident
+        dest.alloc_page().unwrap();
+
+        let mapping = dest.copy_pages_from(&mut src, &[src_ids[1], src_ids[3]]).unwrap();
+        assert_eq!(mapping.len(), 2);
+
+        for (src_id, dest_id) in mapping {
+            let mut src_buf = vec![0u8; page_size as usize];
+            let mut dest_buf = vec![0u8; page_size as usize];
+            src.read_page(src_id, &mut src_buf).unwrap();
+            dest.read_page(dest_id, &mut dest_buf).unwrap();
+            assert_eq!(src_buf, dest_buf);
+        }
+
+        let mut other = DiskPageManager::open(dir.path().join("other.db"), 8192).unwrap();
+        let result = other.copy_pages_from(&mut src, &src_ids);
+        assert!(matches!(result, Err(PageManagerError::PageSizeMismatch { requested: 4096, stored: 8192 })));
+    }
+
+    #[test]
+    fn test_fragmentation_report() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test_fragmentation.db");
+
+        let page_size = 4096;
+        let mut manager = DiskPageManager::open(&db_path, page_size).unwrap();
+        let page_ids: Vec<PageId> = (0..19).map(|_| manager.alloc_page().unwrap()).collect();
+
+        // Every other page: lots of free space, but no runs
+        for &page_id in page_ids.iter().step_by(2).take(5) {
+            manager.free_page(page_id).unwrap();
+        }
+        let report = manager.fragmentation();
+        assert_eq!(report.total_pages, 20);
+        assert_eq!(report.free_pages, 5);
+        assert_eq!(report.free_ratio, 0.25);
+        assert_eq!(report.largest_free_run, 1);
+
+        // A contiguous block of five
+        for &page_id in &page_ids[12..17] {
+            manager.free_page(page_id).unwrap();
+        }
+        let report = manager.fragmentation();
+        assert_eq!(report.free_pages, 10);
+        assert_eq!(report.free_ratio, 0.5);
+        assert_eq!(report.largest_free_run, 5);
+    }
+
+    #[test]
+    fn test_builder_options() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test_builder.db");
+
+        let manager = DiskPageManager::builder()
+            .page_size(8192)
+            .mode(OpenMode::CreateNew)
+            .write_budget(1 << 20)
+            .open(&db_path)
+            .unwrap();
+        assert_eq!(manager.page_size(), 8192);
+        assert_eq!(manager.write_throttle.as_ref().map(|t| t.bytes_per_sec), Some(1 << 20));
+        drop(manager);
+
+        let result = DiskPageManager::builder().mode(OpenMode::CreateNew).open(&db_path);
+        assert!(matches!(result, Err(PageManagerError::AlreadyExists)));
+
+        let manager = DiskPageManager::builder().open(&db_path).unwrap();
+        assert_eq!(manager.page_size(), 8192);
+        assert!(manager.write_throttle.is_none());
+    }
+
+    #[test]
+    fn test_builder_checks_app_id() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test_app_id.db");
+
+        drop(DiskPageManager::builder().app_id(*b"APP1").open(&db_path).unwrap());
+
+        let result = DiskPageManager::builder().app_id(*b"APP2").open(&db_path);
+        assert!(matches!(
+            result,
+            Err(PageManagerError::BadPageFormat(SerializerError::AppIdMismatch { expected: [b'A', b'P', b'P', b'2'], .. }))
+        ));
+
+        assert!(DiskPageManager::builder().app_id(*b"APP1").open(&db_path).is_ok());
+        assert!(DiskPageManager::open_any(&db_path).is_ok());
+    }
+
+    #[test]
+    fn test_observer_sees_reads_and_writes() {
+        use std::sync::{Arc, Mutex};
+
+        struct Recorder(Arc<Mutex<Vec<(&'static str, PageId)>>>);
+
+        impl PageObserver for Recorder {
+            fn on_read(&mut self, page_id: PageId, _data: &[u8]) {
+                self.0.lock().unwrap().push(("read", page_id));
+            }
+
+            fn on_write(&mut self, page_id: PageId, _data: &[u8]) {
+                self.0.lock().unwrap().push(("write", page_id));
+            }
+        }
+
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test_observer.db");
+
+        let page_size = 4096;
+        let mut manager = DiskPageManager::open(&db_path, page_size).unwrap();
+        let events = Arc::new(Mutex::new(vec![]));
+        manager.set_observer(Some(Box::new(Recorder(Arc::clone(&events)))));
+
+        let buf = vec![1u8; page_size as usize];
+        let mut read_buf = vec![0u8; page_size as usize];
+        manager.write_page(1, &buf).unwrap();
+        manager.write_page(2, &buf).unwrap();
+        manager.read_page(1, &mut read_buf).unwrap();
+
+        // A rejected write never reaches the observer
+        assert!(manager.write_page(3, &buf[..10]).is_err());
+
+        assert_eq!(*events.lock().unwrap(), vec![("write", 1), ("write", 2), ("read", 1)]);
+    }
+
+    #[test]
+    fn test_alloc_page_sequential_ids() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test_alloc_sequential.db");
+
+        let page_size = 4096;
+        let mut manager = DiskPageManager::open(&db_path, page_size).unwrap();
+
+        let first = manager.alloc_page().unwrap();
+        let second = manager.alloc_page().unwrap();
+        assert_eq!(first, 1);
+        assert_eq!(second, 2);
+        assert_eq!(manager.header.page_count, 3);
+        assert_eq!(fs::metadata(&db_path).unwrap().len(), 3 * page_size);
+
+        // The new page can be read before anything is written to it
+        let mut read_buf = vec![1u8; page_size as usize];
+        manager.read_page(second, &mut read_buf).unwrap();
+        assert_eq!(read_buf, vec![0u8; page_size as usize]);
+
+        // The page count survives a reopen
+        drop(manager);
+        let manager = DiskPageManager::open(&db_path, page_size).unwrap();
+        assert_eq!(manager.header.page_count, 3);
+    }
+
+    #[test]
+    fn test_open_recovers_torn_header_from_backup() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test_header_backup.db");
+        let page_size = 4096;
+        let root = {
+            let mut manager = DiskPageManager::builder().page_size(page_size).header_backup(true).open(&db_path).unwrap();
+            let root = manager.alloc_page().unwrap();
+            assert_ne!(root, HEADER_BACKUP_PAGE);
+            manager.set_schema_root_page(root).unwrap();
+            manager.sync().unwrap();
+            root
+        };
+
+        // Garble page 0 beyond recognition, magic included
+        let file = OpenOptions::new().read(true).write(true).open(&db_path).unwrap();
+        file.write_at(&[0xAB; HEADER_SERIALIZED_LEN], 0).unwrap();
+
+        let mut manager = DiskPageManager::open(&db_path, page_size).unwrap();
+        assert_eq!(manager.schema_root_page(), root);
+        assert_eq!(manager.header.page_count, root as u64 + 1);
+        assert!(manager.scrub(0, u32::MAX).unwrap().is_empty());
+        drop(manager);
+
+        // Page 0 was rewritten from the backup
+        let mut buf = vec![0u8; HEADER_SERIALIZED_LEN];
+        file.read_at(&mut buf, 0).unwrap();
+        assert_eq!(DatabaseHeader::deserialize(&buf).unwrap().schema_root_page, root as u64);
+    }
+
+    #[test]
+    fn test_open_prefers_newer_backup_header() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test_newer_backup.db");
+        let page_size = 4096;
+        let stale = {
+            let mut manager = DiskPageManager::builder().page_size(page_size).header_backup(true).open(&db_path).unwrap();
+            let stale = manager.header.serialize();
+            manager.set_schema_root_page(7).unwrap();
+            stale
+        };
+
+        // A crash after the backup was synced but before page 0 was rewritten
+        let file = OpenOptions::new().read(true).write(true).open(&db_path).unwrap();
+        file.write_at(&stale, 0).unwrap();
+
+        let manager = DiskPageManager::open(&db_path, page_size).unwrap();
+        assert_eq!(manager.schema_root_page(), 7);
+    }
+
+    #[test]
+    fn test_open_detects_torn_header_write() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test_torn_header.db");
+        {
+            let mut manager = DiskPageManager::open(&db_path, 4096).unwrap();
+            manager.alloc_page().unwrap();
+            manager.sync().unwrap();
+        }
+
+        // Flip a page_count byte without fixing the checksum, as a torn write would
+        let file = OpenOptions::new().read(true).write(true).open(&db_path).unwrap();
+        let mut byte = [0u8; 1];
+        file.read_at(&mut byte, 16).unwrap();
+        file.write_at(&[byte[0] ^ 0x40], 16).unwrap();
+
+        let result = DiskPageManager::open(&db_path, 4096);
+        assert!(matches!(result, Err(PageManagerError::TornHeaderWrite { .. })));
+
+        // A foreign file is still told apart by its magic
+        file.write_at(b"NOPE", 0).unwrap();
+        let result = DiskPageManager::open(&db_path, 4096);
+        assert!(matches!(result, Err(PageManagerError::BadPageFormat(SerializerError::BadMagic(_)))));
+    }
+
+    #[test]
+    fn test_free_page_rejects_double_free_and_header() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test_double_free.db");
+
+        let page_size = 4096;
+        let mut manager = DiskPageManager::open(&db_path, page_size).unwrap();
+        let page_id = manager.alloc_page().unwrap();
+
+        manager.free_page(page_id).unwrap();
+        assert!(matches!(manager.free_page(page_id), Err(PageManagerError::DoubleFree(id)) if id == page_id));
+        assert!(matches!(manager.free_page(0), Err(PageManagerError::InvalidPage(0))));
+        assert!(matches!(manager.free_page(100), Err(PageManagerError::InvalidPage(100))));
+        assert_eq!(manager.freelist, vec![page_id]);
+    }
+
+    #[test]
+    fn test_save_freelist_spanning_several_pages() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test_freelist_chain.db");
+
+        // Tiny pages so the freelist needs a chain: (512 - 4 - 12) / 4 = 124 ids per page
+        let page_size = 512;
+        let mut manager = DiskPageManager::open(&db_path, page_size).unwrap();
+        let page_ids: Vec<PageId> = (0..400).map(|_| manager.alloc_page().unwrap()).collect();
+        for &page_id in page_ids.iter().step_by(2) {
+            manager.free_page(page_id).unwrap();
+        }
+        let mut freed = manager.freelist.clone();
+        manager.close().unwrap();
+        drop(manager);
+
+        let mut manager = DiskPageManager::open(&db_path, page_size).unwrap();
+        assert_ne!(manager.header.freelist_head_page, 0);
+        let mut loaded = manager.freelist.clone();
+        loaded.sort_unstable();
+        freed.sort_unstable();
+        assert_eq!(loaded, freed);
+
+        // Reusing a free page drops the on-disk chain, which may now be overwritten
+        manager.alloc_page().unwrap();
+        assert_eq!(manager.header.freelist_head_page, 0);
+    }
+
+    #[test]
+    fn test_page_checksum_detects_corruption() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test_checksum.db");
+
+        let page_size = 4096;
+        let mut manager = DiskPageManager::open(&db_path, page_size).unwrap();
+        assert_eq!(manager.usable_page_size(), 4092);
+
+        let page_id = manager.alloc_page().unwrap();
+        let untouched = manager.alloc_page().unwrap();
+        manager.write_page(page_id, &vec![5u8; page_size as usize]).unwrap();
+
+        // A never written page is all zeros and reads fine
+        let mut read_buf = vec![0u8; page_size as usize];
+        manager.read_page(untouched, &mut read_buf).unwrap();
+
+        // Flip a payload byte behind the manager's back
+        let file = OpenOptions::new().write(true).open(&db_path).unwrap();
+        file.write_at(&[6u8], page_offset(page_id, page_size) + 100).unwrap();
+
+        let result = manager.read_page(page_id, &mut read_buf);
+        match result {
+            Err(PageManagerError::ChecksumMismatch { page_id: id, expected, found }) => {
+                assert_eq!(id, page_id);
+                assert_ne!(expected, found);
+            }
+            other => panic!("expected a checksum mismatch, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_scrub_reports_corrupted_pages() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test_scrub.db");
+
+        let page_size = 4096;
+        let mut manager = DiskPageManager::open(&db_path, page_size).unwrap();
+        let pages: Vec<PageId> = (0..6).map(|_| manager.alloc_page().unwrap()).collect();
+        for &page_id in &pages {
+            manager.write_page(page_id, &vec![page_id as u8; page_size as usize]).unwrap();
+        }
+        manager.sync().unwrap();
+        assert!(manager.scrub(0, PageId::MAX).unwrap().is_empty());
+
+        // Damage the stored checksums of two pages behind the manager's back
+        let file = OpenOptions::new().write(true).open(&db_path).unwrap();
+        for page_id in [pages[1], pages[4]] {
+            file.write_at(&[0xFF; 4], page_offset(page_id, page_size) + page_size - 4).unwrap();
+        }
+        let before = fs::read(&db_path).unwrap();
+
+        assert_eq!(manager.scrub(0, PageId::MAX).unwrap(), vec![pages[1], pages[4]]);
+        assert_eq!(manager.scrub(pages[2], pages[4]).unwrap(), vec![]);
+        assert_eq!(fs::read(&db_path).unwrap(), before);
+    }
+
+    #[test]
+    fn test_page_cache_hits_misses_and_write_back() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test_page_cache.db");
+
+        let page_size = 4096;
+        let mut manager = DiskPageManager::open_with_cache(&db_path, page_size, 2).unwrap();
+        let page_ids: Vec<PageId> = (0..3).map(|_| manager.alloc_page().unwrap()).collect();
+
+        // Writing a third page evicts the first, which is dirty and must be written back
+        for &page_id in &page_ids {
+            manager.write_page(page_id, &vec![page_id as u8; page_size as usize]).unwrap();
+        }
+        assert_eq!(manager.cache_stats().evictions, 1);
+
+        let mut read_buf = vec![0u8; page_size as usize];
+        manager.read_page(page_ids[2], &mut read_buf).unwrap();
+        assert_eq!(read_buf[0], page_ids[2] as u8);
+        assert_eq!(manager.cache_stats().hits, 1);
+
+        manager.read_page(page_ids[0], &mut read_buf).unwrap();
+        assert_eq!(read_buf[0], page_ids[0] as u8);
+        assert_eq!(manager.cache_stats(), CacheStats { hits: 1, misses: 1, evictions: 2 });
+
+        // Everything reaches the file on sync
+        manager.sync().unwrap();
+        drop(manager);
+        let mut manager = DiskPageManager::open(&db_path, page_size).unwrap();
+        for &page_id in &page_ids {
+            manager.read_page(page_id, &mut read_buf).unwrap();
+            assert_eq!(read_buf[0], page_id as u8);
+        }
+        assert_eq!(manager.cache_stats(), CacheStats::default());
+    }
+
+    #[test]
+    fn test_free_pages_in_range() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test_free_range.db");
+
+        let page_size = 4096;
+        let mut manager = DiskPageManager::open(&db_path, page_size).unwrap();
+        manager.alloc_contiguous(20).unwrap();
+        for page_id in [17, 3, 9, 10, 15, 4] {
+            manager.free_page(page_id).unwrap();
+        }
+
+        assert_eq!(manager.free_pages_in_range(4, 15).unwrap(), vec![4, 9, 10]);
+        assert_eq!(manager.free_pages_in_range(0, 100).unwrap(), vec![3, 4, 9, 10, 15, 17]);
+        assert!(manager.free_pages_in_range(11, 15).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_open_validates_page_size() {
+        let dir = tempdir().unwrap();
+
+        assert!(DiskPageManager::open(dir.path().join("ok.db"), 4096).is_ok());
+
+        for page_size in [4095, 1_000_000] {
+            let path = dir.path().join(format!("bad_{}.db", page_size));
+            let result = DiskPageManager::open(&path, page_size);
+            assert!(matches!(result, Err(PageManagerError::InvalidPageSize(size)) if size == page_size));
+            assert!(!path.exists());
+        }
+    }
+
+    #[test]
+    fn test_open_rejects_foreign_file() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("not_a_yadb.db");
+        let mut bytes = b"SQLite format 3\0".to_vec();
+        bytes.resize(4096, 0);
+        fs::write(&db_path, &bytes).unwrap();
+
+        let result = DiskPageManager::open(&db_path, 4096);
+        assert!(matches!(result, Err(PageManagerError::BadPageFormat(SerializerError::BadMagic(_)))));
+    }
+
+    #[test]
+    fn test_open_rejects_page_size_mismatch() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test_page_size_mismatch.db");
+        drop(DiskPageManager::open(&db_path, 8192).unwrap());
+
+        let result = DiskPageManager::open(&db_path, 4096);
+        assert!(matches!(result, Err(PageManagerError::PageSizeMismatch { requested: 4096, stored: 8192 })));
+
+        let manager = DiskPageManager::open_any(&db_path).unwrap();
+        assert_eq!(manager.page_size(), 8192);
+
+        let missing = dir.path().join("missing.db");
+        assert!(matches!(DiskPageManager::open_any(&missing), Err(PageManagerError::NotFound)));
+    }
+
+    #[test]
+    fn test_memory_page_manager_roundtrip() {
+        let mut manager = MemoryPageManager::new(512);
+        assert_eq!(manager.page_count(), 1);
+
+        let page_id = manager.alloc_page().unwrap();
+        assert_eq!(page_id, 1);
+        let mut buf = vec![1u8; 512];
+        manager.read_page(page_id, &mut buf).unwrap();
+        assert_eq!(buf, vec![0u8; 512]);
+
+        manager.write_page(page_id, &[7u8; 512]).unwrap();
+        manager.read_page(page_id, &mut buf).unwrap();
+        assert_eq!(buf, vec![7u8; 512]);
+
+        assert!(matches!(manager.read_page(5, &mut buf), Err(PageManagerError::InvalidPage(5))));
+        assert!(matches!(manager.write_page(page_id, &[0u8; 10]), Err(PageManagerError::BadPageLength { .. })));
+    }
+
+    #[test]
+    fn test_memory_page_manager_reuses_freed_pages() {
+        let mut manager = MemoryPageManager::new(512);
+        let first = manager.alloc_page().unwrap();
+        let second = manager.alloc_page().unwrap();
+
+        manager.free_page(first).unwrap();
+        assert!(matches!(manager.free_page(first), Err(PageManagerError::DoubleFree(_))));
+        assert!(matches!(manager.free_page(0), Err(PageManagerError::InvalidPage(0))));
+
+        assert_eq!(manager.alloc_page().unwrap(), first);
+        assert_eq!(manager.alloc_page().unwrap(), second + 1);
+        assert_eq!(manager.page_count(), 4);
+    }
+}
@@ -1,6 +1,79 @@
 use std::mem::size_of;
 
 
+/// Lookup table for the reflected CRC-32 polynomial (IEEE 802.3, as used by zlib and gzip)
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// CRC-32 (IEEE) checksum of `bytes`, used to detect corrupted pages.
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc = CRC32_TABLE[((crc ^ byte as u32) & 0xFF) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+/// Current on-disk header format version. Version 2 added the header checksum, version 3 the
+/// sequence number and backup slot.
+pub const HEADER_VERSION: u32 = 3;
+
+/// Offset of the `u32` CRC32 that follows the header fields, computed over everything before it
+const HEADER_CRC_OFFSET: usize = 60;
+
+/// Where the CRC32 sits in version 1 and 2 headers, which end after the application id
+const LEGACY_HEADER_CRC_OFFSET: usize = 44;
+
+/// Bytes a version 1 or 2 header takes
+const LEGACY_HEADER_LEN: usize = LEGACY_HEADER_CRC_OFFSET + size_of::<u32>();
+
+/// Smallest supported page size
+pub const MIN_PAGE_SIZE: u64 = 512;
+
+/// Largest supported page size
+pub const MAX_PAGE_SIZE: u64 = 65536;
+
+/// Check that `page_size` is a power of two between `MIN_PAGE_SIZE` and `MAX_PAGE_SIZE`, so
+/// pages line up with filesystem blocks and the layout math has room to work with.
+pub fn validate_page_size(page_size: u64) -> Result<(), SerializerError> {
+    if !page_size.is_power_of_two() || !(MIN_PAGE_SIZE..=MAX_PAGE_SIZE).contains(&page_size) {
+        return Err(SerializerError::InvalidPageSize(page_size));
+    }
+    Ok(())
+}
+
+/// Bytes the serialized header takes at the start of page 0: magic, version, page size, page
+/// count, freelist head, schema root, app id, sequence number, backup page and the header CRC.
+/// This is the on-disk format size, independent of how Rust lays out `DatabaseHeader` in memory.
+pub const HEADER_SERIALIZED_LEN: usize = 64;
+
+// The field widths have to add up to the format size, and the CRC has to be the last field
+const _: () = assert!(
+    size_of::<[u8; 4]>() + size_of::<u32>() + 4 * size_of::<u64>() + size_of::<[u8; 4]>() == LEGACY_HEADER_CRC_OFFSET
+);
+const _: () = assert!(LEGACY_HEADER_CRC_OFFSET + 2 * size_of::<u64>() == HEADER_CRC_OFFSET);
+const _: () = assert!(HEADER_CRC_OFFSET + size_of::<u32>() == HEADER_SERIALIZED_LEN);
+
+/// Copy `N` bytes starting at `offset` into an array. Callers check the length up front.
+fn read_array<const N: usize>(bytes: &[u8], offset: usize) -> [u8; N] {
+    let mut out = [0u8; N];
+    out.copy_from_slice(&bytes[offset..offset + N]);
+    out
+}
+
 /// Database header structure.
 /// This structure is used to store metadata about the database file.
 /// It will be stored on the first page of the database file. As header actually uses less bytes
@@ -32,25 +105,43 @@ pub struct DatabaseHeader {
 
     /// ID of the schema root page. Schema is a B-tree that contains all the metadata about the tables, indexes, etc.
     pub schema_root_page: u64,
+
+    /// Application id chosen by whoever embeds YADB, so one application doesn't open another's
+    /// files by mistake. All zeros when the creator didn't set one.
+    pub app_id: [u8; 4],
+
+    /// Bumped on every header write, so `open` can tell which of page 0 and the backup slot
+    /// is newer. Not stored before version 3.
+    pub sequence: u64,
+
+    /// Page holding a copy of the header that is written before page 0, or 0 if the database
+    /// keeps none. Not stored before version 3.
+    pub backup_page: u64,
 }
 
 impl DatabaseHeader {
     /// Creates a new `DatabaseHeader` with the specified page size for a new database file.
     /// For existing files, read it from file and use `DatabaseHeader::deserialize`.
-    pub fn new(page_size: u64) -> DatabaseHeader {
-        DatabaseHeader {
+    /// Fails with `SerializerError::InvalidPageSize` unless `validate_page_size` accepts the size.
+    pub fn new(page_size: u64) -> Result<DatabaseHeader, SerializerError> {
+        validate_page_size(page_size)?;
+        Ok(DatabaseHeader {
             magic: *b"YADB", // Magic number for YADB
-            version: 1,
+            version: HEADER_VERSION,
             page_size,
             page_count: 1,
             freelist_head_page: 0,
             schema_root_page: 0,
-        }
+            app_id: [0; 4],
+            sequence: 0,
+            backup_page: 0,
+        })
     }
     
-    /// Serializes the `DatabaseHeader` into a byte array.
+    /// Serializes the `DatabaseHeader` into `HEADER_SERIALIZED_LEN` bytes, or into the shorter
+    /// layout without a sequence number and backup page for version 1 and 2 headers.
     pub fn serialize(&self) -> Vec<u8> {
-        let mut buffer = Vec::with_capacity(size_of::<DatabaseHeader>());
+        let mut buffer = Vec::with_capacity(HEADER_SERIALIZED_LEN);
 
         buffer.extend_from_slice(&self.magic);
         buffer.extend_from_slice(&self.version.to_le_bytes());
@@ -58,33 +149,105 @@ impl DatabaseHeader {
         buffer.extend_from_slice(&self.page_count.to_le_bytes());
         buffer.extend_from_slice(&self.freelist_head_page.to_le_bytes());
         buffer.extend_from_slice(&self.schema_root_page.to_le_bytes());
-
-        buffer.resize(self.page_size as usize, 0);
+        buffer.extend_from_slice(&self.app_id);
+        if self.version >= 3 {
+            buffer.extend_from_slice(&self.sequence.to_le_bytes());
+            buffer.extend_from_slice(&self.backup_page.to_le_bytes());
+        }
+        let crc = crc32(&buffer);
+        buffer.extend_from_slice(&crc.to_le_bytes());
 
         buffer
     }
     
     /// Deserializes a byte array into a `DatabaseHeader`.
+    ///
+    /// The checksum after the header fields is verified first, so a garbled header is rejected
+    /// before its `page_size` is used to size anything. Version 1 files predate the checksum
+    /// and leave it zero, which is accepted as unchecked.
     pub fn deserialize(bytes: &[u8]) -> Result<Self, SerializerError> {
-        if bytes.len() < size_of::<Self>() {
-            return Err(SerializerError::InsufficientData("Insufficient data to deserialize DatabaseHeader".to_string()));
+        if bytes.len() < LEGACY_HEADER_LEN {
+            return Err(SerializerError::TooShort { needed: HEADER_SERIALIZED_LEN, got: bytes.len() });
+        }
+
+        let magic = read_array(bytes, 0);
+        if &magic != b"YADB" {
+            return Err(SerializerError::BadMagic(magic));
+        }
+        let version = u32::from_le_bytes(read_array(bytes, 4));
+        if version == 0 || version > HEADER_VERSION {
+            return Err(SerializerError::UnsupportedVersion(version));
+        }
+
+        let crc_offset = if version >= 3 { HEADER_CRC_OFFSET } else { LEGACY_HEADER_CRC_OFFSET };
+        if bytes.len() < crc_offset + size_of::<u32>() {
+            return Err(SerializerError::TooShort { needed: HEADER_SERIALIZED_LEN, got: bytes.len() });
+        }
+
+        let stored_crc = u32::from_le_bytes(read_array(bytes, crc_offset));
+        if version >= 2 || stored_crc != 0 {
+            let computed_crc = crc32(&bytes[..crc_offset]);
+            if stored_crc != computed_crc {
+                return Err(SerializerError::ChecksumMismatch { expected: stored_crc, found: computed_crc });
+            }
         }
 
         Ok(Self {
-            magic: <[u8; 4]>::try_from(&bytes[0..4]).unwrap(),
-            version: u32::from_le_bytes(bytes[4..8].try_into().unwrap()),
-            page_size: u64::from_le_bytes(bytes[8..16].try_into().unwrap()),
-            page_count: u64::from_le_bytes(bytes[16..24].try_into().unwrap()),
-            freelist_head_page: u64::from_le_bytes(bytes[24..32].try_into().unwrap()),
-            schema_root_page: u64::from_le_bytes(bytes[32..40].try_into().unwrap()),
+            magic,
+            version,
+            page_size: u64::from_le_bytes(read_array(bytes, 8)),
+            page_count: u64::from_le_bytes(read_array(bytes, 16)),
+            freelist_head_page: u64::from_le_bytes(read_array(bytes, 24)),
+            schema_root_page: u64::from_le_bytes(read_array(bytes, 32)),
+            app_id: read_array(bytes, 40),
+            sequence: if version >= 3 { u64::from_le_bytes(read_array(bytes, 44)) } else { 0 },
+            backup_page: if version >= 3 { u64::from_le_bytes(read_array(bytes, 52)) } else { 0 },
         })
     }
+
+    /// Check that the file belongs to the application identified by `expected`.
+    pub fn check_app_id(&self, expected: [u8; 4]) -> Result<(), SerializerError> {
+        if self.app_id != expected {
+            return Err(SerializerError::AppIdMismatch { expected, found: self.app_id });
+        }
+        Ok(())
+    }
 }
 
+/// Errors raised while decoding on-disk structures
 #[derive(Debug)]
 pub enum SerializerError {
     /// Insufficient data to deserialize
     InsufficientData(String),
+    /// Buffer is shorter than the structure being deserialized
+    TooShort {
+        /// Bytes the structure needs
+        needed: usize,
+        /// Bytes that were provided
+        got: usize,
+    },
+    /// The file doesn't start with the `YADB` magic, so it isn't a YADB database
+    BadMagic([u8; 4]),
+    /// Header format version this build can't read
+    UnsupportedVersion(u32),
+    /// Page size that isn't a power of two between `MIN_PAGE_SIZE` and `MAX_PAGE_SIZE`
+    InvalidPageSize(u64),
+    /// Page type tag that doesn't match any `PageType`
+    UnknownPageType(u8),
+    /// The header's stored checksum doesn't match its contents
+    ChecksumMismatch {
+        /// Checksum stored in the header
+        expected: u32,
+        /// Checksum computed over the header fields
+        found: u32,
+    },
+    /// The header's application id isn't the one the caller expected
+    AppIdMismatch {
+        /// Application id the caller asked for
+        expected: [u8; 4],
+        /// Application id stored in the header
+        found: [u8; 4],
+    },
 }
 
 impl std::fmt::Display for SerializerError {
@@ -99,13 +262,63 @@ impl std::error::Error for SerializerError {
     }
 }
 
-struct FreeListPage {
-    page_size: u64,
-    next_page: u64,
-    freelist: Vec<u64>,
+/// Kind of data a page holds, stored as a one-byte tag where a page format has one
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageType {
+    /// Database metadata, i.e. the header page
+    Meta = 0,
+    /// B-tree internal node
+    Internal = 1,
+    /// B-tree leaf node
+    Leaf = 2,
+    /// Page of the freelist chain
+    Freelist = 3,
+    /// Continuation of a value too large for its page
+    Overflow = 4,
+    /// B-tree leaf node whose keys are front-coded against the previous key
+    FrontCodedLeaf = 5,
+}
+
+impl PageType {
+    /// Decode a page type tag, failing with `SerializerError::UnknownPageType` for unknown values.
+    pub fn from_u8(tag: u8) -> Result<Self, SerializerError> {
+        match tag {
+            0 => Ok(PageType::Meta),
+            1 => Ok(PageType::Internal),
+            2 => Ok(PageType::Leaf),
+            3 => Ok(PageType::Freelist),
+            4 => Ok(PageType::Overflow),
+            5 => Ok(PageType::FrontCodedLeaf),
+            _ => Err(SerializerError::UnknownPageType(tag)),
+        }
+    }
+
+    /// The tag byte stored on disk for this page type.
+    pub fn to_u8(self) -> u8 {
+        self as u8
+    }
+}
+
+/// Page of the on-disk freelist chain.
+///
+/// Layout: `u32` number of entries, `u64` id of the next freelist page (0 ends the chain),
+/// then the packed `u32` ids of free pages filling the rest of the page.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FreeListPage {
+    /// Size of the page this is serialized into
+    pub page_size: u64,
+    /// ID of the next page in the chain, 0 if this is the last one
+    pub next_page: u64,
+    /// Free page ids stored in this page
+    pub freelist: Vec<u32>,
 }
 
 impl FreeListPage {
+    /// Bytes taken by the count and next-page fields
+    const HEADER_LEN: usize = 4 + 8;
+
+    /// Creates an empty freelist page that ends the chain.
     pub fn new(page_size: u64) -> Self {
         Self { 
             page_size, 
@@ -114,50 +327,237 @@ impl FreeListPage {
         }
     }
 
+    /// Number of page ids that fit in a single freelist page of `page_size` bytes.
+    pub fn capacity(page_size: u64) -> usize {
+        (page_size as usize).saturating_sub(Self::HEADER_LEN) / 4
+    }
+
+    /// Serializes the page into a `page_size` byte buffer.
     pub fn serialize(&self) -> Vec<u8> {
-        let mut buffer = Vec::with_capacity(size_of::<Self>());
+        let mut buffer = Vec::with_capacity(self.page_size as usize);
+        buffer.extend_from_slice(&(self.freelist.len() as u32).to_le_bytes());
+        buffer.extend_from_slice(&self.next_page.to_le_bytes());
         for page_id in &self.freelist {
             buffer.extend_from_slice(&page_id.to_le_bytes());
         }
 
+        buffer.resize(self.page_size as usize, 0);
+
         buffer
     }
     
+    /// Deserializes a freelist page, checking the declared count fits in the page.
     pub fn deserialize(bytes: &[u8], page_size: u64) -> Result<Self, SerializerError> {
-        if bytes.len() < size_of::<Self>() {
-            return Err(SerializerError::InsufficientData("Insufficient data to deserialize FreeListPage".to_string()));
+        if bytes.len() < Self::HEADER_LEN {
+            return Err(SerializerError::TooShort { needed: Self::HEADER_LEN, got: bytes.len() });
         }
 
-        let next_page = u64::from_le_bytes(bytes[0..8].try_into().unwrap());
-
-        let mut freelist = vec![];
-        for i in 8..bytes.len() / 8 {
-            freelist.push(u64::from_le_bytes(bytes[i * 8..(i + 1) * 8].try_into().unwrap()));
+        let count = u32::from_le_bytes(read_array(bytes, 0)) as usize;
+        let next_page = u64::from_le_bytes(read_array(bytes, 4));
+        if Self::HEADER_LEN + count * 4 > bytes.len() {
+            return Err(SerializerError::InsufficientData(format!("FreeListPage declares {} entries, more than fit in the page", count)));
         }
 
+        let freelist = bytes[Self::HEADER_LEN..Self::HEADER_LEN + count * 4]
+            .chunks_exact(4)
+            .map(|chunk| u32::from_le_bytes(read_array(chunk, 0)))
+            .collect();
+
         Ok(Self { page_size, freelist, next_page })
     }
 } 
 
+/// Index of a record in a `SlottedPage`'s slot directory
+pub type SlotId = u32;
+
+/// Page holding variable-length records.
+///
+/// Layout: `u32` slot count and `u32` offset where the record area starts, then the slot
+/// directory of `(u32 offset, u32 length)` entries growing from the front, while record bytes
+/// are packed against the end of the page and grow backwards. A deleted record leaves a
+/// tombstone slot (offset 0) so other slot ids stay stable; tombstones are reused by later
+/// inserts, and the space of deleted records is reclaimed by compacting when needed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SlottedPage {
+    data: Vec<u8>,
+}
+
+impl SlottedPage {
+    /// Bytes taken by the slot count and record area offset
+    const HEADER_LEN: usize = 4 + 4;
+    /// Bytes taken by one slot directory entry
+    const SLOT_LEN: usize = 4 + 4;
+
+    /// Creates an empty page of `page_size` bytes.
+    pub fn new(page_size: usize) -> Self {
+        let mut page = Self { data: vec![0u8; page_size] };
+        page.set_record_start(page_size);
+        page
+    }
+
+    /// Wraps the bytes of a page written by `as_bytes`, checking that its header and slot
+    /// directory stay within the page.
+    pub fn from_bytes(data: Vec<u8>) -> Result<Self, SerializerError> {
+        if data.len() < Self::HEADER_LEN {
+            return Err(SerializerError::TooShort { needed: Self::HEADER_LEN, got: data.len() });
+        }
+        let page = Self { data };
+        let directory_end = Self::HEADER_LEN + page.slot_count() as usize * Self::SLOT_LEN;
+        if directory_end > page.record_start() || page.record_start() > page.data.len() {
+            return Err(SerializerError::InsufficientData("SlottedPage header points outside the page".to_string()));
+        }
+        for slot in 0..page.slot_count() {
+            let (offset, len) = page.slot(slot);
+            if offset != 0 && (offset < page.record_start() || offset + len > page.data.len()) {
+                return Err(SerializerError::InsufficientData(format!("SlottedPage slot {} points outside the record area", slot)));
+            }
+        }
+        Ok(page)
+    }
+
+    /// Raw page bytes, ready to be written out.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Number of slots, including tombstones.
+    pub fn slot_count(&self) -> u32 {
+        u32::from_le_bytes(read_array(&self.data, 0))
+    }
+
+    /// Contiguous free bytes between the slot directory and the record area.
+    pub fn free_space(&self) -> usize {
+        self.record_start() - self.directory_end()
+    }
+
+    /// Free bytes after compacting, i.e. including the space of deleted records.
+    pub fn reclaimable_space(&self) -> usize {
+        let live: usize = (0..self.slot_count())
+            .map(|slot| self.slot(slot))
+            .filter(|&(offset, _)| offset != 0)
+            .map(|(_, len)| len)
+            .sum();
+        self.data.len() - self.directory_end() - live
+    }
+
+    /// Store `bytes` as a new record, returning its slot, or `None` if it doesn't fit even
+    /// after compacting.
+    pub fn insert_record(&mut self, bytes: &[u8]) -> Option<SlotId> {
+        let tombstone = (0..self.slot_count()).find(|&slot| self.slot(slot).0 == 0);
+        let needed = bytes.len() + if tombstone.is_some() { 0 } else { Self::SLOT_LEN };
+        if needed > self.reclaimable_space() {
+            return None;
+        }
+        if needed > self.free_space() {
+            self.compact();
+        }
+
+        let slot = tombstone.unwrap_or_else(|| {
+            let slot = self.slot_count();
+            self.data[0..4].copy_from_slice(&(slot + 1).to_le_bytes());
+            slot
+        });
+        let offset = self.record_start() - bytes.len();
+        self.data[offset..offset + bytes.len()].copy_from_slice(bytes);
+        self.set_record_start(offset);
+        // Records sit after the header, so a live record's offset is never the tombstone's 0
+        self.set_slot(slot, offset, bytes.len());
+        Some(slot)
+    }
+
+    /// The record stored in `slot`, or `None` if the slot doesn't exist or was deleted.
+    pub fn get_record(&self, slot: SlotId) -> Option<&[u8]> {
+        if slot >= self.slot_count() {
+            return None;
+        }
+        match self.slot(slot) {
+            (0, _) => None,
+            (offset, len) => Some(&self.data[offset..offset + len]),
+        }
+    }
+
+    /// Delete the record in `slot`, leaving a tombstone. Returns whether there was a record.
+    pub fn delete_record(&mut self, slot: SlotId) -> bool {
+        if self.get_record(slot).is_none() {
+            return false;
+        }
+        self.set_slot(slot, 0, 0);
+        true
+    }
+
+    /// Repack the live records against the end of the page, keeping their slot ids
+    fn compact(&mut self) {
+        let records: Vec<(SlotId, Vec<u8>)> = (0..self.slot_count())
+            .filter_map(|slot| self.get_record(slot).map(|record| (slot, record.to_vec())))
+            .collect();
+        let mut offset = self.data.len();
+        for (slot, record) in records {
+            offset -= record.len();
+            self.data[offset..offset + record.len()].copy_from_slice(&record);
+            self.set_slot(slot, offset, record.len());
+        }
+        self.set_record_start(offset);
+    }
+
+    fn record_start(&self) -> usize {
+        u32::from_le_bytes(read_array(&self.data, 4)) as usize
+    }
+
+    fn set_record_start(&mut self, offset: usize) {
+        self.data[4..8].copy_from_slice(&(offset as u32).to_le_bytes());
+    }
+
+    fn directory_end(&self) -> usize {
+        Self::HEADER_LEN + self.slot_count() as usize * Self::SLOT_LEN
+    }
+
+    fn slot(&self, slot: SlotId) -> (usize, usize) {
+        let at = Self::HEADER_LEN + slot as usize * Self::SLOT_LEN;
+        let offset = u32::from_le_bytes(read_array(&self.data, at)) as usize;
+        let len = u32::from_le_bytes(read_array(&self.data, at + 4)) as usize;
+        (offset, len)
+    }
+
+    fn set_slot(&mut self, slot: SlotId, offset: usize, len: usize) {
+        let at = Self::HEADER_LEN + slot as usize * Self::SLOT_LEN;
+        self.data[at..at + 4].copy_from_slice(&(offset as u32).to_le_bytes());
+        self.data[at + 4..at + 8].copy_from_slice(&(len as u32).to_le_bytes());
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
     fn test_serialize() {
-        let header = DatabaseHeader::new(4096);
+        let header = DatabaseHeader::new(4096).unwrap();
         let bytes = header.serialize();
         
         // Expected sizes
-        assert_eq!(bytes.len(), size_of::<DatabaseHeader>());
+        assert_eq!(bytes.len(), HEADER_SERIALIZED_LEN);
         
         // Check individual fields are serialized correctly
         assert_eq!(&bytes[0..4], b"YADB"); // magic
-        assert_eq!(&bytes[4..8], &(1_u32).to_le_bytes()); // version
+        assert_eq!(&bytes[4..8], &HEADER_VERSION.to_le_bytes()); // version
         assert_eq!(&bytes[8..16], &(4096_u64).to_le_bytes()); // page_size
         assert_eq!(&bytes[16..24], &(1_u64).to_le_bytes()); // page_count
         assert_eq!(&bytes[24..32], &(0_u64).to_le_bytes()); // freelist_head_page
         assert_eq!(&bytes[32..40], &(0_u64).to_le_bytes()); // schema_page
+        assert_eq!(&bytes[40..44], &[0; 4]); // app_id
+        assert_eq!(&bytes[44..52], &(0_u64).to_le_bytes()); // sequence
+        assert_eq!(&bytes[52..60], &(0_u64).to_le_bytes()); // backup_page
+        assert_eq!(&bytes[60..64], &crc32(&bytes[..60]).to_le_bytes()); // header_crc
+    }
+
+    #[test]
+    fn test_page_size_validation() {
+        assert!(DatabaseHeader::new(4096).is_ok());
+        assert!(DatabaseHeader::new(512).is_ok());
+        assert!(DatabaseHeader::new(65536).is_ok());
+        assert!(matches!(DatabaseHeader::new(4095), Err(SerializerError::InvalidPageSize(4095))));
+        assert!(matches!(DatabaseHeader::new(1_000_000), Err(SerializerError::InvalidPageSize(1_000_000))));
+        assert!(matches!(DatabaseHeader::new(256), Err(SerializerError::InvalidPageSize(256))));
     }
 
     #[test]
@@ -169,8 +569,11 @@ mod tests {
         bytes.extend_from_slice(&(100_u64).to_le_bytes()); // page_count
         bytes.extend_from_slice(&(0_u64).to_le_bytes()); // freelist_head_page
         bytes.extend_from_slice(&(0_u64).to_le_bytes()); // schema_page
+        bytes.extend_from_slice(&[0; 4]); // app_id
+        let crc = crc32(&bytes);
+        bytes.extend_from_slice(&crc.to_le_bytes()); // header_crc
 
-        bytes.resize(size_of::<DatabaseHeader>(), 0); // Ensure the buffer is the right size
+        assert_eq!(bytes.len(), LEGACY_HEADER_LEN);
 
         let header = DatabaseHeader::deserialize(&bytes).unwrap();
         
@@ -186,11 +589,14 @@ mod tests {
     fn test_serialize_deserialize_roundtrip() {
         let original = DatabaseHeader {
             magic: *b"YADB",
-            version: 3,
+            version: HEADER_VERSION,
             page_size: 16384,
             page_count: 500,
             freelist_head_page: 0,
             schema_root_page: 0,
+            app_id: *b"APP1",
+            sequence: 42,
+            backup_page: 1,
         };
 
         let bytes = original.serialize();
@@ -199,10 +605,141 @@ mod tests {
         assert_eq!(original, deserialized);
     }
 
+    #[test]
+    fn test_deserialize_rejects_corrupt_header() {
+        let mut bytes = DatabaseHeader::new(4096).unwrap().serialize();
+        bytes[9] ^= 0xFF; // garble page_size
+        let result = DatabaseHeader::deserialize(&bytes);
+        assert!(matches!(result, Err(SerializerError::ChecksumMismatch { .. })));
+    }
+
+    #[test]
+    fn test_deserialize_accepts_unchecked_v1_header() {
+        let mut header = DatabaseHeader::new(4096).unwrap();
+        header.version = 1;
+        let mut bytes = header.serialize();
+        bytes[44..48].copy_from_slice(&[0; 4]);
+        assert_eq!(DatabaseHeader::deserialize(&bytes).unwrap(), header);
+
+        bytes[9] ^= 0xFF;
+        assert!(DatabaseHeader::deserialize(&bytes).is_ok());
+
+        header.version = 2;
+        let mut bytes = header.serialize();
+        bytes[44..48].copy_from_slice(&[0; 4]);
+        assert!(DatabaseHeader::deserialize(&bytes).is_err());
+    }
+
     #[test]
     fn test_deserialize_with_insufficient_data() {
         let bytes = [0u8; 20]; // Not enough bytes for a full header
         let result = DatabaseHeader::deserialize(&bytes);
-        assert!(result.is_err());
+        assert!(matches!(result, Err(SerializerError::TooShort { got: 20, .. })));
+    }
+
+    #[test]
+    fn test_deserialize_rejects_foreign_magic() {
+        let mut bytes = DatabaseHeader::new(4096).unwrap().serialize();
+        bytes[0..4].copy_from_slice(b"SQLi");
+        let result = DatabaseHeader::deserialize(&bytes);
+        assert!(matches!(result, Err(SerializerError::BadMagic(magic)) if &magic == b"SQLi"));
+    }
+
+    #[test]
+    fn test_deserialize_rejects_unsupported_version() {
+        let mut header = DatabaseHeader::new(4096).unwrap();
+        header.version = HEADER_VERSION + 1;
+        let result = DatabaseHeader::deserialize(&header.serialize());
+        assert!(matches!(result, Err(SerializerError::UnsupportedVersion(v)) if v == HEADER_VERSION + 1));
+    }
+
+    #[test]
+    fn test_freelist_page_roundtrip() {
+        let page = FreeListPage {
+            page_size: 4096,
+            next_page: 7,
+            freelist: vec![3, 9, 12],
+        };
+
+        let bytes = page.serialize();
+        assert_eq!(bytes.len(), 4096);
+        assert_eq!(FreeListPage::deserialize(&bytes, 4096).unwrap(), page);
+        assert_eq!(FreeListPage::capacity(4096), 1021);
+    }
+
+    #[test]
+    fn test_freelist_page_rejects_oversized_count() {
+        let mut bytes = FreeListPage::new(64).serialize();
+        bytes[0..4].copy_from_slice(&100_u32.to_le_bytes());
+        assert!(FreeListPage::deserialize(&bytes, 64).is_err());
+    }
+
+    #[test]
+    fn test_crc32_known_values() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(b"The quick brown fox jumps over the lazy dog"), 0x414F_A339);
+    }
+
+    #[test]
+    fn test_slotted_page_insert_get_delete() {
+        let mut page = SlottedPage::new(128);
+        let first = page.insert_record(b"hello").unwrap();
+        let second = page.insert_record(b"world!").unwrap();
+        let empty = page.insert_record(b"").unwrap();
+
+        assert_eq!(page.get_record(first), Some(&b"hello"[..]));
+        assert_eq!(page.get_record(second), Some(&b"world!"[..]));
+        assert_eq!(page.get_record(empty), Some(&b""[..]));
+        assert_eq!(page.free_space(), 128 - 8 - 3 * 8 - 11);
+
+        assert!(page.delete_record(first));
+        assert!(!page.delete_record(first));
+        assert_eq!(page.get_record(first), None);
+        assert_eq!(page.get_record(second), Some(&b"world!"[..]));
+
+        // The tombstone is reused, so existing slot ids don't shift
+        assert_eq!(page.insert_record(b"again").unwrap(), first);
+        assert_eq!(page.slot_count(), 3);
+        assert_eq!(page.get_record(99), None);
+    }
+
+    #[test]
+    fn test_slotted_page_compacts_when_full() {
+        let mut page = SlottedPage::new(64);
+        let a = page.insert_record(&[1u8; 20]).unwrap();
+        let b = page.insert_record(&[2u8; 20]).unwrap();
+        assert_eq!(page.insert_record(&[3u8; 20]), None);
+
+        page.delete_record(a);
+        assert_eq!(page.free_space(), 64 - 8 - 16 - 40);
+        assert_eq!(page.reclaimable_space(), 64 - 8 - 16 - 20);
+
+        let c = page.insert_record(&[3u8; 20]).unwrap();
+        assert_eq!(c, a);
+        assert_eq!(page.get_record(b), Some(&[2u8; 20][..]));
+        assert_eq!(page.get_record(c), Some(&[3u8; 20][..]));
+    }
+
+    #[test]
+    fn test_slotted_page_from_bytes() {
+        let mut page = SlottedPage::new(256);
+        page.insert_record(b"record").unwrap();
+        let restored = SlottedPage::from_bytes(page.as_bytes().to_vec()).unwrap();
+        assert_eq!(restored, page);
+
+        let mut bytes = page.as_bytes().to_vec();
+        bytes[0..4].copy_from_slice(&1000u32.to_le_bytes());
+        assert!(SlottedPage::from_bytes(bytes).is_err());
+    }
+
+    #[test]
+    fn test_page_type_tags() {
+        for page_type in [PageType::Meta, PageType::Internal, PageType::Leaf, PageType::Freelist, PageType::Overflow, PageType::FrontCodedLeaf] {
+            assert_eq!(PageType::from_u8(page_type.to_u8()).unwrap(), page_type);
+        }
+        assert_eq!(PageType::Leaf.to_u8(), 2);
+        assert!(matches!(PageType::from_u8(6), Err(SerializerError::UnknownPageType(6))));
+        assert!(matches!(PageType::from_u8(255), Err(SerializerError::UnknownPageType(255))));
     }
 }
@@ -0,0 +1,345 @@
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::page_manager::{DiskPageManager, PageId, PageManagerError};
+use crate::pages::crc32;
+
+/// Magic bytes at the start of every WAL file
+const WAL_MAGIC: &[u8; 4] = b"YWAL";
+
+/// Bytes before the first page image: magic, `u64` page size, `u32` schema root and `u32`
+/// page count
+const WAL_HEADER_LEN: usize = 4 + 8 + 4 + 4;
+
+/// Path of the write-ahead log belonging to the database at `db_path`: the same path with
+/// `.wal` appended.
+pub fn wal_path_for(db_path: &Path) -> PathBuf {
+    let mut path = db_path.as_os_str().to_owned();
+    path.push(".wal");
+    PathBuf::from(path)
+}
+
+/// Encode the after-images of a committing batch.
+///
+/// Layout: `YWAL` magic, `u64` page size, `u32` schema root to record (0 to leave it alone),
+/// `u32` page count, then each page as its `u32` id followed by the page bytes, and finally a
+/// CRC32 of everything before it. The trailing CRC doubles as the commit marker: a WAL cut
+/// short by a crash doesn't verify and is ignored.
+fn encode(page_size: u64, schema_root: PageId, pages: &BTreeMap<PageId, Vec<u8>>) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(WAL_HEADER_LEN + pages.len() * (4 + page_size as usize) + 4);
+    buf.extend_from_slice(WAL_MAGIC);
+    buf.extend_from_slice(&page_size.to_le_bytes());
+    buf.extend_from_slice(&schema_root.to_le_bytes());
+    buf.extend_from_slice(&(pages.len() as u32).to_le_bytes());
+    for (page_id, data) in pages {
+        buf.extend_from_slice(&page_id.to_le_bytes());
+        buf.extend_from_slice(data);
+    }
+    let crc = crc32(&buf);
+    buf.extend_from_slice(&crc.to_le_bytes());
+    buf
+}
+
+/// Contents of a committed WAL
+struct LoggedBatch {
+    /// Schema root to record, 0 to leave the header's alone
+    schema_root: PageId,
+    /// After-image of every page written by the batch
+    pages: Vec<(PageId, Vec<u8>)>,
+}
+
+/// Decode a WAL written by `encode`, returning `None` if it is incomplete or corrupt.
+fn decode(bytes: &[u8], page_size: u64) -> Option<LoggedBatch> {
+    let (body, crc) = bytes.split_last_chunk::<4>()?;
+    if body.len() < WAL_HEADER_LEN || crc32(body) != u32::from_le_bytes(*crc) || &body[..4] != WAL_MAGIC {
+        return None;
+    }
+    if u64::from_le_bytes(body[4..12].try_into().ok()?) != page_size {
+        return None;
+    }
+
+    let schema_root = PageId::from_le_bytes(body[12..16].try_into().ok()?);
+    let count = u32::from_le_bytes(body[16..20].try_into().ok()?) as usize;
+    let entry_len = 4 + page_size as usize;
+    let entries = &body[WAL_HEADER_LEN..];
+    if entries.len() != count * entry_len {
+        return None;
+    }
+    let pages = entries
+        .chunks_exact(entry_len)
+        .map(|entry| {
+            let page_id = PageId::from_le_bytes(entry[..4].try_into().ok()?);
+            Some((page_id, entry[4..].to_vec()))
+        })
+        .collect::<Option<_>>()?;
+    Some(LoggedBatch { schema_root, pages })
+}
+
+/// Bring the database back to a consistent state after a crash by applying a committed WAL
+/// left behind next to it, then clearing the WAL. Returns whether anything was replayed.
+///
+/// A WAL that doesn't verify belongs to a batch that never finished committing. Its
+/// pages were not written to the database yet, so it is simply discarded.
+pub(crate) fn replay(manager: &mut DiskPageManager) -> Result<bool, PageManagerError> {
+    let path = manager.wal_path().to_path_buf();
+    let bytes = match fs::read(&path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e.into()),
+    };
+    if bytes.is_empty() {
+        return Ok(false);
+    }
+
+    let batch = decode(&bytes, manager.page_size());
+    if let Some(batch) = &batch {
+        apply(manager, batch.schema_root, batch.pages.iter().map(|(page_id, data)| (*page_id, data)))?;
+    }
+    clear(&path)?;
+    Ok(batch.is_some())
+}
+
+/// Write logged pages to the database and sync them, then record `schema_root` (unless 0) in
+/// the header and sync again, so the header never points at pages that aren't on disk
+fn apply<'d>(
+    manager: &mut DiskPageManager,
+    schema_root: PageId,
+    pages: impl Iterator<Item = (PageId, &'d Vec<u8>)>,
+) -> Result<(), PageManagerError> {
+    for (page_id, data) in pages {
+        manager.write_page(page_id, data)?;
+    }
+    manager.sync()?;
+    if schema_root != 0 {
+        manager.set_schema_root_page(schema_root)?;
+        manager.sync()?;
+    }
+    Ok(())
+}
+
+/// Empty the WAL at `path` once its contents are safely in the database
+fn clear(path: &Path) -> Result<(), PageManagerError> {
+    let file = OpenOptions::new().write(true).open(path)?;
+    file.set_len(0)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+/// Group of page writes, and optionally a new schema root, that reach the database all
+/// together or not at all. Obtained from `DiskPageManager::begin_batch`; B-tree transactions
+/// commit through one.
+///
+/// Writes are buffered in memory and visible to the batch's own reads. `commit` logs their
+/// after-images to the WAL and syncs it before touching the database, so a crash mid-way is
+/// repaired by replaying the WAL on the next open. Dropping the batch without committing
+/// discards it.
+///
+/// Page allocation and freeing still go straight to the page manager.
+pub struct WalBatch<'a> {
+    manager: &'a mut DiskPageManager,
+    dirty: BTreeMap<PageId, Vec<u8>>,
+    schema_root: PageId,
+}
+
+impl<'a> WalBatch<'a> {
+    /// Start an empty batch on `manager`
+    pub(crate) fn new(manager: &'a mut DiskPageManager) -> Self {
+        Self { manager, dirty: BTreeMap::new(), schema_root: 0 }
+    }
+
+    /// Read page `page_id` into `buf`, seeing this batch's own uncommitted writes.
+    pub fn read_page(&mut self, page_id: PageId, buf: &mut [u8]) -> Result<(), PageManagerError> {
+        match self.dirty.get(&page_id) {
+            Some(data) => {
+                self.check_len(buf.len())?;
+                buf.copy_from_slice(data);
+                Ok(())
+            }
+            None => self.manager.read_page(page_id, buf),
+        }
+    }
+
+    /// Buffer a write of `buf` (exactly page_size bytes) to page `page_id` until commit.
+    /// The header page can't be written through a batch, see `set_schema_root_page`.
+    pub fn write_page(&mut self, page_id: PageId, buf: &[u8]) -> Result<(), PageManagerError> {
+        self.check_len(buf.len())?;
+        if page_id == 0 {
+            return Err(PageManagerError::InvalidPage(page_id));
+        }
+        self.dirty.insert(page_id, buf.to_vec());
+        Ok(())
+    }
+
+    /// Record `page_id` as the schema root on commit, after the batch's pages are on disk.
+    pub fn set_schema_root_page(&mut self, page_id: PageId) -> Result<(), PageManagerError> {
+        if page_id == 0 {
+            return Err(PageManagerError::InvalidPage(page_id));
+        }
+        self.schema_root = page_id;
+        Ok(())
+    }
+
+    /// Number of distinct pages written so far.
+    pub fn dirty_pages(&self) -> usize {
+        self.dirty.len()
+    }
+
+    /// Make every buffered write durable: log them to the WAL and sync it, write the pages
+    /// to the database and sync it, record the schema root if one was set, then clear the WAL.
+    pub fn commit(self) -> Result<(), PageManagerError> {
+        if self.dirty.is_empty() && self.schema_root == 0 {
+            return Ok(());
+        }
+
+        let wal_path = self.manager.wal_path().to_path_buf();
+        let mut wal = OpenOptions::new().write(true).create(true).truncate(true).open(&wal_path)?;
+        wal.write_all(&encode(self.manager.page_size(), self.schema_root, &self.dirty))?;
+        wal.sync_all()?;
+
+        apply(self.manager, self.schema_root, self.dirty.iter().map(|(page_id, data)| (*page_id, data)))?;
+        clear(&wal_path)
+    }
+
+    /// Discard every buffered write. Same as dropping the batch.
+    pub fn rollback(self) {}
+
+    /// Reject buffers that aren't exactly one page long
+    fn check_len(&self, len: usize) -> Result<(), PageManagerError> {
+        let expected = self.manager.page_size();
+        if len as u64 != expected {
+            return Err(PageManagerError::BadPageLength { expected, got: len as u64 });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_commit_persists_writes() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test_wal_commit.db");
+
+        let mut manager = DiskPageManager::open(&db_path, 4096).unwrap();
+        let page_id = manager.alloc_page().unwrap();
+
+        let mut batch = manager.begin_batch();
+        batch.write_page(page_id, &[7u8; 4096]).unwrap();
+        batch.set_schema_root_page(page_id).unwrap();
+        let mut buf = vec![0u8; 4096];
+        batch.read_page(page_id, &mut buf).unwrap();
+        assert_eq!(buf, vec![7u8; 4096]);
+        batch.commit().unwrap();
+        drop(manager);
+
+        assert_eq!(fs::metadata(wal_path_for(&db_path)).unwrap().len(), 0);
+        let mut manager = DiskPageManager::open(&db_path, 4096).unwrap();
+        assert_eq!(manager.schema_root_page(), page_id);
+        manager.read_page(page_id, &mut buf).unwrap();
+        assert!(buf[..4092].iter().all(|&b| b == 7));
+    }
+
+    #[test]
+    fn test_rollback_discards_writes() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test_wal_rollback.db");
+
+        let mut manager = DiskPageManager::open(&db_path, 4096).unwrap();
+        let page_id = manager.alloc_page().unwrap();
+        manager.write_page(page_id, &[1u8; 4096]).unwrap();
+
+        let mut batch = manager.begin_batch();
+        batch.write_page(page_id, &[2u8; 4096]).unwrap();
+        batch.rollback();
+
+        {
+            let mut batch = manager.begin_batch();
+            batch.write_page(page_id, &[3u8; 4096]).unwrap();
+        }
+
+        let mut buf = vec![0u8; 4096];
+        manager.read_page(page_id, &mut buf).unwrap();
+        assert!(buf[..4092].iter().all(|&b| b == 1));
+        assert!(matches!(manager.begin_batch().write_page(0, &[0u8; 4096]), Err(PageManagerError::InvalidPage(0))));
+    }
+
+    #[test]
+    fn test_open_replays_committed_wal() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test_wal_replay.db");
+
+        let page_id = {
+            let mut manager = DiskPageManager::open(&db_path, 4096).unwrap();
+            manager.alloc_page().unwrap()
+        };
+
+        // Crash after the WAL was synced but before the pages reached the database
+        let pages = BTreeMap::from([(page_id, vec![9u8; 4096])]);
+        fs::write(wal_path_for(&db_path), encode(4096, page_id, &pages)).unwrap();
+
+        let mut manager = DiskPageManager::open(&db_path, 4096).unwrap();
+        assert_eq!(manager.schema_root_page(), page_id);
+        let mut buf = vec![0u8; 4096];
+        manager.read_page(page_id, &mut buf).unwrap();
+        assert!(buf[..4092].iter().all(|&b| b == 9));
+        assert_eq!(fs::metadata(wal_path_for(&db_path)).unwrap().len(), 0);
+    }
+
+    #[test]
+    fn test_open_ignores_torn_wal() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test_wal_torn.db");
+
+        let page_id = {
+            let mut manager = DiskPageManager::open(&db_path, 4096).unwrap();
+            let page_id = manager.alloc_page().unwrap();
+            manager.write_page(page_id, &[1u8; 4096]).unwrap();
+            page_id
+        };
+
+        // Crash while the WAL itself was being written
+        let pages = BTreeMap::from([(page_id, vec![9u8; 4096])]);
+        let wal = encode(4096, 0, &pages);
+        fs::write(wal_path_for(&db_path), &wal[..wal.len() - 100]).unwrap();
+
+        let mut manager = DiskPageManager::open(&db_path, 4096).unwrap();
+        let mut buf = vec![0u8; 4096];
+        manager.read_page(page_id, &mut buf).unwrap();
+        assert!(buf[..4092].iter().all(|&b| b == 1));
+        assert_eq!(fs::metadata(wal_path_for(&db_path)).unwrap().len(), 0);
+    }
+
+    #[test]
+    fn test_close_with_checkpoint_truncates_wal() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test_wal_checkpoint.db");
+        let wal_path = wal_path_for(&db_path);
+
+        // A commit that failed after logging leaves its batch in the WAL of an open database
+        let mut manager = DiskPageManager::open(&db_path, 4096).unwrap();
+        let page_id = manager.alloc_page().unwrap();
+        let pages = BTreeMap::from([(page_id, vec![9u8; 4096])]);
+        fs::write(&wal_path, encode(4096, page_id, &pages)).unwrap();
+        manager.close().unwrap();
+        drop(manager);
+        assert!(fs::metadata(&wal_path).unwrap().len() > 0);
+
+        let mut manager = DiskPageManager::builder().page_size(4096).checkpoint_on_close(true).open(&db_path).unwrap();
+        fs::write(&wal_path, encode(4096, page_id, &pages)).unwrap();
+        manager.close().unwrap();
+        drop(manager);
+        assert_eq!(fs::metadata(&wal_path).unwrap().len(), 0);
+
+        let mut manager = DiskPageManager::open(&db_path, 4096).unwrap();
+        assert!(!manager.checkpoint().unwrap());
+        assert_eq!(manager.schema_root_page(), page_id);
+        let mut buf = vec![0u8; 4096];
+        manager.read_page(page_id, &mut buf).unwrap();
+        assert!(buf[..4092].iter().all(|&b| b == 9));
+    }
+}